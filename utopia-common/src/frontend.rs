@@ -1,6 +1,108 @@
 use serde::{Deserialize, Serialize};
 
 pub use crate::library::{self, LibraryItemFrontend, LibraryItemFrontendDetails, LibraryItemModule};
+use crate::module;
+
+/// version of the protocol between µCore and its frontends
+pub type ProtocolVersion = u32;
+
+/// newest protocol version
+///
+/// Every [FrontendEvent] and [CoreEvent] carries the version agreed on
+/// in the handshake. Actions added to the protocol are only sent to
+/// frontends that agreed on a version knowing them, item stati added
+/// later are left out for them (see [downgrade](CoreActions::downgrade)).
+///
+/// - 2: subscriptions, library queries, updating, installing and
+///   uninstalling items, closing single processes or everything, pids
+///   of items
+pub const PROTOCOL_VERSION: ProtocolVersion = 2;
+/// protocol versions µCore speaks, oldest first
+pub const PROTOCOL_VERSIONS: &[ProtocolVersion] = &[1, PROTOCOL_VERSION];
+
+/// module id µCore answers
+/// [RequestPreferenceDiag](FrontendActions::RequestPreferenceDiag) and
+/// [PreferenceDiagUpdate](FrontendActions::PreferenceDiagUpdate) for
+/// itself. [DiagType::Item](library::preferences::DiagType::Item) are
+/// the launch options of an item, [DiagType::Module](library::preferences::DiagType::Module)
+/// the ones of every item.
+pub const CORE_ID: &str = "com.github.projekt-utopia.core";
+
+/// first message a frontend sends after connecting, instead of a
+/// [FrontendEvent]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Handshake {
+	/// human-readable name of the frontend
+	pub name: String,
+	/// version of the frontend itself, for logging
+	pub client_version: String,
+	/// protocol versions the frontend speaks, µCore picks the newest one
+	/// both sides know
+	pub protocol_versions: Vec<ProtocolVersion>,
+	/// optional features the frontend would like to use
+	#[serde(default)]
+	pub features: Vec<String>
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HandshakeAccepted {
+	/// unique id µCore assigned to this connection
+	pub connection_id: String,
+	/// the name from the [Handshake], which doesn't have to be unique
+	pub name: String,
+	pub core_version: String,
+	pub protocol_version: ProtocolVersion,
+	/// the requested features µCore supports
+	pub features: Vec<String>
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HandshakeRejected {
+	pub reason: String,
+	/// protocol versions µCore speaks
+	pub protocol_versions: Vec<ProtocolVersion>
+}
+
+/// kinds of events frontends can subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Topic {
+	/// items added, changed or removed, and modules asking for a refresh
+	Library,
+	/// status changes of items other than running
+	ItemStatus,
+	/// items being launched and their processes exiting
+	Processes,
+	/// modules being loaded, unloaded or restarted
+	Modules,
+	/// preferences changed by a frontend
+	Preferences,
+	/// progress of downloads and other long running tasks
+	Progress
+}
+impl Topic {
+	pub const ALL: &'static [Topic] = &[
+		Topic::Library,
+		Topic::ItemStatus,
+		Topic::Processes,
+		Topic::Modules,
+		Topic::Preferences,
+		Topic::Progress
+	];
+}
+
+/// events of topics to receive, optionally only those about some
+/// items or modules. Events that are not about any item or module
+/// aren't filtered.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Subscription {
+	pub topics: Vec<Topic>,
+	/// uuids of items, all items if empty
+	#[serde(default)]
+	pub items: Vec<String>,
+	/// ids of modules, all modules if empty
+	#[serde(default)]
+	pub modules: Vec<String>
+}
 
 // Frontend --> Core
 #[derive(Debug, Deserialize, Serialize)]
@@ -13,42 +115,146 @@ pub enum FrontendActions {
 	PreferenceDiagUpdate(
 		(String, library::preferences::DiagType),
 		std::collections::HashMap<String, library::preferences::FieldType>
-	)
+	),
+	/// uuid of module
+	UnloadModule(String),
+	/// uuid of module, it's loaded again from the same library path.
+	/// Note that libraries that can't be unmapped by the dynamic
+	/// linker (e.g. ones using thread local destructors) still run the
+	/// old code afterwards.
+	ReloadModule(String),
+	/// replaces the filters of the given topics. Frontends are
+	/// subscribed to every topic without filters when they connect.
+	Subscribe(Subscription),
+	/// topics to stop receiving, every topic if empty
+	Unsubscribe(Vec<Topic>),
+	QueryLibrary(library::query::LibraryQuery),
+	/// (module id, operation id) of a cancellable
+	/// [Progress](module::Progress)
+	CancelOperation(String, String)
+}
+impl FrontendActions {
+	/// protocol version that introduced the action
+	pub fn since(&self) -> ProtocolVersion {
+		match self {
+			FrontendActions::Subscribe(_)
+			| FrontendActions::Unsubscribe(_)
+			| FrontendActions::QueryLibrary(_)
+			| FrontendActions::CancelOperation(..)
+			| FrontendActions::GameMethod(
+				library::LibraryItemProviderMethods::Install(..)
+				| library::LibraryItemProviderMethods::GetPid(_)
+				| library::LibraryItemProviderMethods::Close(
+					library::LibraryItemProviderQuitActions::Specific(_) | library::LibraryItemProviderQuitActions::All
+				)
+				| library::LibraryItemProviderMethods::Kill(
+					library::LibraryItemProviderQuitActions::Specific(_) | library::LibraryItemProviderQuitActions::All
+				)
+			) => 2,
+			_ => 1
+		}
+	}
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct FrontendEvent {
-	pub version: String,
+	/// the protocol version agreed on in the handshake
+	pub version: ProtocolVersion,
 	pub uuid: Option<String>,
 	pub action: FrontendActions
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ModuleState {
+	Loaded,
+	Unloaded,
+	/// the module's thread returned unexpectedly
+	Crashed,
+	/// the module is restarted in n seconds
+	Restarting(u64),
+	/// the module died because a dependency did, it's restarted once
+	/// the dependency is back
+	WaitingForDependency,
+	/// the module failed too often and won't be restarted
+	Failed
+}
+
 // Core --> Frontend
 #[derive(Debug, Serialize, Deserialize)]
 pub enum CoreActions {
-	SignalSuccessHandshake(String),
+	SignalSuccessHandshake(HandshakeAccepted),
+	/// the connection is closed after this
+	SignalHandshakeRejected(HandshakeRejected),
 	ResponseGameLibrary(Vec<LibraryItemFrontend>),
 	ResponseFullGameLibrary(Vec<LibraryItemFrontendDetails>),
 	ResponseItemDetails(library::LibraryItemDetails),
 	ResponseGameUpdate(LibraryItemFrontend),
+	/// uuid of the item that is no longer in the library
+	SignalItemRemoved(String),
+	/// uuid of module, new state
+	SignalModuleState(String, ModuleState),
 	//SignalGameLaunch(String),
 	PreferenceDiagResponse(
 		(String, library::preferences::DiagType),
 		library::preferences::PreferenceDiag
 	),
-	Error(String, String)
+	Error(String, String),
+	/// uuid of a module whose items changed in ways that weren't
+	/// signaled, frontends should request the library again
+	SignalRefresh(String),
+	/// a frontend changed the preferences of (module, diag)
+	SignalPreferencesChanged((String, library::preferences::DiagType)),
+	ResponseLibraryQuery(library::query::LibraryPage),
+	/// (module id, progress) of an operation of a module
+	SignalProgress((String, module::Progress)),
+	/// (uuid, reason) updating, installing or uninstalling the item
+	/// failed
+	SignalItemError(String, String),
+	/// (uuid, crash) a process of the item ended unexpectedly
+	SignalItemCrashed(String, library::Crash),
+	/// (uuid, [(pid, module id)]) processes the item runs as, answering
+	/// [GetPid](library::LibraryItemProviderMethods::GetPid)
+	ResponsePids(String, Vec<(u32, String)>)
+}
+impl CoreActions {
+	/// protocol version that introduced the action
+	pub fn since(&self) -> ProtocolVersion {
+		match self {
+			CoreActions::SignalRefresh(_)
+			| CoreActions::SignalPreferencesChanged(_)
+			| CoreActions::ResponseLibraryQuery(_)
+			| CoreActions::SignalProgress(_)
+			| CoreActions::SignalItemError(..)
+			| CoreActions::SignalItemCrashed(..)
+			| CoreActions::ResponsePids(..) => 2,
+			_ => 1
+		}
+	}
+
+	/// drops what frontends speaking version don't know from the items
+	/// of the action
+	pub fn downgrade(&mut self, version: ProtocolVersion) {
+		match self {
+			CoreActions::ResponseGameLibrary(items) => items.iter_mut().for_each(|item| item.downgrade(version)),
+			CoreActions::ResponseFullGameLibrary(items) => items.iter_mut().for_each(|item| item.downgrade(version)),
+			CoreActions::ResponseGameUpdate(item) => item.downgrade(version),
+			CoreActions::ResponseLibraryQuery(page) => page.items.iter_mut().for_each(|item| item.downgrade(version)),
+			_ => ()
+		}
+	}
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CoreEvent {
-	pub version: String,
+	/// the protocol version agreed on in the handshake
+	pub version: ProtocolVersion,
 	pub uuid: Option<String>,
 	pub action: CoreActions
 }
 impl CoreEvent {
 	pub fn new(action: CoreActions, uuid: Option<String>) -> Self {
 		CoreEvent {
-			version: String::from("0.0.0"),
+			version: PROTOCOL_VERSION,
 			uuid,
 			action
 		}
@@ -0,0 +1,44 @@
+//! Protocol between µCore and out-of-process modules
+//!
+//! An out-of-process module is an executable named `*.module` that
+//! µCore spawns with its stdin and stdout connected to the core. Both sides write one
+//! JSON encoded message per line: µCore writes [CoreMessages](CoreMessage)
+//! to the module's stdin, the module writes
+//! [ModuleMessages](ModuleMessage) to its stdout.
+//!
+//! 1. the module introduces itself with [Hello](ModuleMessage::Hello)
+//! 2. µCore answers with [Init](CoreMessage::Init) once it decided to
+//!    keep the module
+//! 3. both sides exchange commands until µCore closes the module's
+//!    stdin, which is the request to stop
+//! 4. the module reports why its thread returned with
+//!    [Died](ModuleMessage::Died) or [Error](ModuleMessage::Error) and
+//!    exits
+use serde::{Deserialize, Serialize};
+
+use crate::module::{CoreCommands, ModuleCommands, ModuleInfo, ThreadDeathExcuse};
+
+// Core --> Module
+#[derive(Debug, Serialize, Deserialize)]
+pub enum CoreMessage {
+	/// connection url of the µtopia database
+	Init { database: String },
+	Command(CoreCommands)
+}
+
+// Module --> Core
+#[derive(Debug, Serialize, Deserialize)]
+#[allow(clippy::large_enum_variant)]
+pub enum ModuleMessage {
+	Hello {
+		id: String,
+		/// module interface version as `major.minor`
+		abi_version: String,
+		info: ModuleInfo
+	},
+	Command(ModuleCommands),
+	/// the module thread returned
+	Died(ThreadDeathExcuse),
+	/// the module thread failed
+	Error(String)
+}
@@ -1,3 +1,4 @@
 pub mod frontend;
+pub mod ipc;
 pub mod library;
 pub mod module;
@@ -36,3 +36,34 @@ pub struct AgeRating {
 	pub esrb_rating: Option<ESRBRating>,
 	pub fsk_rating: Option<FSKRating>
 }
+impl AgeRating {
+	/// age in years the strictest of the ratings requires, None if the
+	/// item isn't rated yet
+	pub fn min_age(&self) -> Option<u8> {
+		let pegi = self.pegi_rating.map(|rating| match rating {
+			PEGIRating::Pegi3 => 3,
+			PEGIRating::Pegi7 => 7,
+			PEGIRating::Pegi12 => 12,
+			PEGIRating::Pegi16 => 16,
+			PEGIRating::Pegi18 => 18
+		});
+		let esrb = self.esrb_rating.and_then(|rating| match rating {
+			ESRBRating::EsrbRatingPending => None,
+			ESRBRating::EsrbEarlyChildhood => Some(3),
+			ESRBRating::EsrbEveryone => Some(6),
+			ESRBRating::EsrbEveryone10Plus => Some(10),
+			ESRBRating::EsrbTeen => Some(13),
+			ESRBRating::EsrbMature => Some(17),
+			ESRBRating::EsrbAdult => Some(18)
+		});
+		let fsk = self.fsk_rating.and_then(|rating| match rating {
+			FSKRating::FskRatingPending => None,
+			FSKRating::Fsk0 => Some(0),
+			FSKRating::Fsk6 => Some(6),
+			FSKRating::Fsk12 => Some(12),
+			FSKRating::Fsk16 => Some(16),
+			FSKRating::Fsk18 => Some(18)
+		});
+		[pegi, esrb, fsk].iter().flatten().copied().max()
+	}
+}
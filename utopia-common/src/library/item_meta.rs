@@ -16,7 +16,7 @@ pub enum GameModes {
 	Other(String)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Genre {
 	PointAndClick,
 	Fighting,
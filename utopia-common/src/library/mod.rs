@@ -4,11 +4,14 @@ pub mod credits;
 pub mod item_meta;
 
 pub mod preferences;
+pub mod query;
 
-use std::collections::HashMap;
+use std::{collections::HashMap, ffi::OsStr, path::PathBuf, process::Command};
 
 use serde::{Deserialize, Serialize};
 
+use crate::frontend::ProtocolVersion;
+
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum LibraryItemKind {
 	Game,
@@ -18,10 +21,23 @@ pub enum LibraryItemKind {
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum LibraryItemStatus {
 	Running(Option<u32>),
+	/// asked to close, until its processes exited or are killed
 	Closing,
 	Updatable,
 	Updating,
-	Installed
+	Installed,
+	/// owned, but has to be installed before it can be launched
+	NotInstalled,
+	Installing
+}
+impl LibraryItemStatus {
+	/// protocol version that introduced the status
+	pub fn since(&self) -> ProtocolVersion {
+		match self {
+			LibraryItemStatus::NotInstalled | LibraryItemStatus::Installing => 2,
+			_ => 1
+		}
+	}
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,10 +48,13 @@ pub struct LibraryItemDetails {
 	pub genre: Vec<item_meta::Genre>,
 	pub game_modes: Vec<item_meta::GameModes>,
 	pub credits: credits::Credits,
-	pub controller_support: Vec<item_meta::InputType>
+	pub controller_support: Vec<item_meta::InputType>,
+	/// free-form labels, e.g. from the store the item is from
+	#[serde(default)]
+	pub tags: Vec<String>
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct LibraryItemModule {
 	pub uuid: String,
 	pub name: String,
@@ -44,6 +63,16 @@ pub struct LibraryItemModule {
 	pub status: Vec<LibraryItemStatus>
 }
 
+/// changes a module makes to one of its items, fields that are None
+/// are left as they are
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LibraryItemPatch {
+	pub uuid: String,
+	pub name: Option<String>,
+	pub details: Option<LibraryItemDetails>,
+	pub status: Option<Vec<LibraryItemStatus>>
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LibraryItemFrontend {
 	pub uuid: String,
@@ -51,7 +80,43 @@ pub struct LibraryItemFrontend {
 	pub kind: LibraryItemKind,
 	// (uuid, title, stati)
 	pub active_provider: LibraryProvider,
-	pub providers: HashMap<String, LibraryProvider>
+	pub providers: HashMap<String, LibraryProvider>,
+	/// unix timestamp of the last launch
+	#[serde(default)]
+	pub last_played: Option<u64>,
+	/// seconds the item ran in total
+	#[serde(default)]
+	pub playtime: u64,
+	#[serde(default)]
+	pub last_crash: Option<Crash>
+}
+impl LibraryItemFrontend {
+	/// drops the stati frontends speaking version don't know
+	pub fn downgrade(&mut self, version: ProtocolVersion) {
+		self.active_provider.downgrade(version);
+		self.providers.values_mut().for_each(|provider| provider.downgrade(version));
+	}
+}
+
+/// what is known about a process of an item that ended unexpectedly
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CrashInfo {
+	pub exit_code: Option<i32>,
+	/// signal that terminated the process
+	pub signal: Option<i32>,
+	/// last lines the item logged
+	pub log: Option<String>
+}
+
+/// a crash recorded on an item
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Crash {
+	/// unix timestamp
+	pub time: u64,
+	/// module id of the provider that ran the item
+	pub provider: String,
+	pub info: CrashInfo
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -59,18 +124,28 @@ pub struct LibraryProvider {
 	pub uuid: String,
 	pub name: String,
 	pub icon: Option<String>,
-	pub stati: Vec<LibraryItemStatus>
+	pub stati: Vec<LibraryItemStatus>,
+	/// the item was restored from the database, but the module didn't
+	/// confirm it yet (e.g. because it isn't loaded)
+	#[serde(default)]
+	pub stale: bool
 }
 
 impl LibraryProvider {
-	pub fn new(uuid: String, name: String, icon: Option<String>, stati: Vec<LibraryItemStatus>) -> Self {
+	pub fn new(uuid: String, name: String, icon: Option<String>, stati: Vec<LibraryItemStatus>, stale: bool) -> Self {
 		Self {
 			uuid,
 			name,
 			icon,
-			stati
+			stati,
+			stale
 		}
 	}
+
+	/// drops the stati frontends speaking version don't know
+	pub fn downgrade(&mut self, version: ProtocolVersion) {
+		self.stati.retain(|status| status.since() <= version);
+	}
 }
 
 impl PartialEq for LibraryProvider {
@@ -88,13 +163,22 @@ pub struct LibraryItemFrontendDetails {
 	pub active_provider: LibraryProvider,
 	pub providers: HashMap<String, LibraryProvider>
 }
+impl LibraryItemFrontendDetails {
+	/// drops the stati frontends speaking version don't know
+	pub fn downgrade(&mut self, version: ProtocolVersion) {
+		self.active_provider.downgrade(version);
+		self.providers.values_mut().for_each(|provider| provider.downgrade(version));
+	}
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum LibraryItemProviderQuitActions {
 	ActiveProvider(String),
 	// uuid of game, uuid of provider
 	OfProvider(String, String),
+	/// one process of an item, by the pid its module reported
 	Specific(u32),
+	/// every running item of every provider
 	All
 }
 
@@ -105,9 +189,70 @@ pub enum LibraryItemProviderMethods {
 	LaunchViaProvider(String, String),
 	// uuid of game, uuid of provider
 	ChangeSelectedProvider(String, String),
+	/// sends SIGTERM to the item's processes, or asks its module to
+	/// close it, and kills whatever is left after a timeout
 	Close(LibraryItemProviderQuitActions),
+	/// uuid of game, answered with
+	/// [ResponsePids](crate::frontend::CoreActions::ResponsePids)
 	GetPid(String),
 	Kill(LibraryItemProviderQuitActions),
 	Update(String),
-	Uninstall(String)
+	Uninstall(String),
+	// uuid of game, uuid of provider
+	Install(String, String, InstallOptions)
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InstallOptions {
+	/// absolute path to install to, the module picks one if None
+	pub target_dir: Option<PathBuf>
+}
+
+/// how an item is started, set by the user for every item and for
+/// single ones
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LaunchOptions {
+	/// variables set in addition to the inherited environment
+	pub env: HashMap<String, String>,
+	/// appended to the arguments the item is started with
+	pub args: Vec<String>,
+	/// command and arguments the item is started through, e.g.
+	/// `["gamemoderun"]` or `["prime-run"]`
+	pub wrapper: Vec<String>
+}
+impl LaunchOptions {
+	/// item's options on top of these: its variables win, its arguments
+	/// come last and its wrapper runs inside of these ones
+	pub fn merged(&self, item: &LaunchOptions) -> LaunchOptions {
+		let mut env = self.env.clone();
+		env.extend(item.env.iter().map(|(key, value)| (key.clone(), value.clone())));
+		LaunchOptions {
+			env,
+			args: self.args.iter().chain(&item.args).cloned().collect(),
+			wrapper: self.wrapper.iter().chain(&item.wrapper).cloned().collect()
+		}
+	}
+
+	/// command starting program with args and the options applied.
+	/// Modules starting items themselves should use it, so options
+	/// behave the same for every provider.
+	pub fn command<P, I, S>(&self, program: P, args: I) -> Command
+	where
+		P: AsRef<OsStr>,
+		I: IntoIterator<Item = S>,
+		S: AsRef<OsStr>
+	{
+		let mut command = match self.wrapper.split_first() {
+			Some((wrapper, wrapper_args)) => {
+				let mut command = Command::new(wrapper);
+				command.args(wrapper_args).arg(program);
+				command
+			},
+			None => Command::new(program)
+		};
+		command.args(args).args(&self.args).envs(&self.env);
+		command
+	}
 }
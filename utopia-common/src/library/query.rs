@@ -0,0 +1,74 @@
+//! Searching the library, see
+//! [QueryLibrary](crate::frontend::FrontendActions::QueryLibrary)
+use serde::{Deserialize, Serialize};
+
+use super::{item_meta::Genre, LibraryItemFrontend, LibraryItemKind, LibraryItemStatus};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum NameFilter {
+	/// case insensitive substring
+	Contains(String),
+	/// case insensitive, the characters have to appear in order but not
+	/// necessarily next to each other
+	Fuzzy(String)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SortBy {
+	Name,
+	/// unix timestamp of the last launch
+	LastPlayed,
+	Playtime,
+	/// how well the name matches the [NameFilter], best first. Sorts by
+	/// name without one.
+	Relevance
+}
+
+/// every filter that is set has to match, lists match if any of their
+/// entries does
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LibraryQuery {
+	pub name: Option<NameFilter>,
+	pub kinds: Vec<LibraryItemKind>,
+	pub genres: Vec<Genre>,
+	/// module ids providing the item
+	pub providers: Vec<String>,
+	/// status of any provider; `Running(None)` matches every running item
+	pub status: Vec<LibraryItemStatus>,
+	/// highest age rating in years, items without a rating don't match
+	pub max_age: Option<u8>,
+	pub tags: Vec<String>,
+	pub sort: SortBy,
+	pub descending: bool,
+	/// items per page, at least one, all if None
+	pub limit: Option<usize>,
+	/// [next_cursor](LibraryPage::next_cursor) of the previous page
+	pub cursor: Option<String>
+}
+impl Default for LibraryQuery {
+	fn default() -> Self {
+		LibraryQuery {
+			name: None,
+			kinds: Vec::new(),
+			genres: Vec::new(),
+			providers: Vec::new(),
+			status: Vec::new(),
+			max_age: None,
+			tags: Vec::new(),
+			sort: SortBy::Name,
+			descending: false,
+			limit: None,
+			cursor: None
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryPage {
+	pub items: Vec<LibraryItemFrontend>,
+	/// items matching the query on all pages
+	pub total: usize,
+	/// continues after the last item of this page, None on the last page
+	pub next_cursor: Option<String>
+}
@@ -1,41 +1,142 @@
+use serde::{Deserialize, Serialize};
+
 pub use crate::library::{self, LibraryItemModule as LibraryItem};
 
 /// Signals that the module sends to the core to notify about progress
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum LibraryItemStatusSignals {
 	/// A LibaryItem has launched (uuid, pid)
+	///
+	/// µCore tracks the processes pid spawns after this signal as part
+	/// of the item. Without cgroup support it relies on the process
+	/// group instead, so items should be started in one of their own
+	/// (see `std::os::unix::process::CommandExt::process_group`).
 	Launched(String, u32),
-	// (uuid)
+	/// every process of the item exited (uuid)
 	Closed(String),
-	// (uuid)
-	Crashed(String)
+	/// like [Closed](LibraryItemStatusSignals::Closed), but the item
+	/// ended unexpectedly (uuid, info)
+	Crashed(String, library::CrashInfo),
+	/// the update finished and the item is installed (uuid)
+	Updated(String),
+	/// the item is still installed in the old version (uuid, reason)
+	UpdateFailed(String, String),
+	/// (uuid) the item is no longer installed. To drop it from the
+	/// library as well use
+	/// [RemoveLibraryItem](ModuleCommands::RemoveLibraryItem).
+	Uninstalled(String),
+	/// (uuid, reason)
+	UninstallFailed(String, String),
+	/// the item is installed and can be launched (uuid)
+	Installed(String),
+	/// the item is still not installed (uuid, reason)
+	InstallFailed(String, String)
 }
 
 // Core --> Module
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum CoreCommands {
 	Reload,
-	LaunchLibraryItem(String),
+	/// (uuid, options) the options are the user's ones for every item
+	/// merged with the item's, see
+	/// [LaunchOptions::command](library::LaunchOptions::command)
+	LaunchLibraryItem(String, library::LaunchOptions),
+	/// (uuid) sent instead of SIGTERM to modules that close their items
+	/// themselves (see [closes_items](ModuleInfo::closes_items)), and
+	/// for items launched without a pid. Items still running after the
+	/// close timeout are killed.
 	CloseLibraryItem(String),
+	/// (uuid) answered with [Progress](ModuleCommands::Progress) and
+	/// then [Updated](LibraryItemStatusSignals::Updated) or
+	/// [UpdateFailed](LibraryItemStatusSignals::UpdateFailed)
+	UpdateLibraryItem(String),
+	/// (uuid) answered with
+	/// [Uninstalled](LibraryItemStatusSignals::Uninstalled) or
+	/// [UninstallFailed](LibraryItemStatusSignals::UninstallFailed)
+	UninstallLibraryItem(String),
+	/// (uuid, options) answered with
+	/// [Progress](ModuleCommands::Progress) and then
+	/// [Installed](LibraryItemStatusSignals::Installed) or
+	/// [InstallFailed](LibraryItemStatusSignals::InstallFailed)
+	InstallLibraryItem(String, library::InstallOptions),
 	RequestPreferenceDiag(library::preferences::DiagType),
 	PreferenceDiagUpdate(
 		library::preferences::DiagType,
 		std::collections::HashMap<String, library::preferences::FieldType>
-	)
+	),
+	/// (operation id) only sent for operations reported as cancellable
+	CancelOperation(String)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum OperationKind {
+	Download,
+	Verify,
+	Install,
+	Update,
+	Sync
+}
+
+/// state of a long-running operation of a module
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Progress {
+	/// chosen by the module, unique among its unfinished operations
+	pub operation: String,
+	/// uuid of the library item, None for operations like syncing the
+	/// whole library
+	pub item: Option<String>,
+	pub kind: OperationKind,
+	/// bytes done so far
+	pub done: u64,
+	/// None while the size is unknown
+	pub total: Option<u64>,
+	/// bytes per second
+	pub rate: Option<u64>,
+	/// whether [CancelOperation](CoreCommands::CancelOperation) is
+	/// honored
+	pub cancellable: bool,
+	/// the operation ended, successfully or not. No more progress
+	/// follows for its id.
+	pub finished: bool
+}
+impl Progress {
+	/// seconds until the operation is done at the current rate
+	pub fn eta(&self) -> Option<u64> {
+		match (self.total, self.rate) {
+			(Some(total), Some(rate)) if rate > 0 => Some(total.saturating_sub(self.done) / rate),
+			_ => None
+		}
+	}
 }
 
 // Module --> Core
-#[derive(Debug)]
+//
+// new variants are only ever appended. Module processes built against
+// an older minor interface version still send the old ones, library
+// modules have to match the version exactly, see
+// utopia_module::InterfaceVersion
+#[derive(Debug, Serialize, Deserialize)]
+#[allow(clippy::large_enum_variant)]
 pub enum ModuleCommands {
 	Refresh,
 	AddLibraryItem(LibraryItem),
 	AddLibraryItemBulk(Vec<LibraryItem>),
 	ItemStatusSignal(LibraryItemStatusSignals),
-	PreferenceDiagResponse(library::preferences::DiagType, library::preferences::PreferenceDiag)
+	PreferenceDiagResponse(library::preferences::DiagType, library::preferences::PreferenceDiag),
+	/// (uuid) the module no longer provides the item, e.g. because it
+	/// was uninstalled
+	RemoveLibraryItem(String),
+	UpdateLibraryItem(library::LibraryItemPatch),
+	/// every item the module provides. Items missing from it are
+	/// removed from the module.
+	ReplaceAllItems(Vec<LibraryItem>),
+	/// reported as often as the module likes, µCore throttles what is
+	/// passed on to frontends
+	Progress(Progress)
 }
 
 /// The return value of the module thread
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum ThreadDeathExcuse {
 	/// If a runtime dependency dies and the module is unable to
 	/// restart it
@@ -46,13 +147,19 @@ pub enum ThreadDeathExcuse {
 	Other(String)
 }
 
-/// Human readable information about a module. Returned by the
-/// get_module_info function (outside of the thread)
+/// Human readable information about a module, and how µCore treats
+/// it. Returned by the get_module_info function (outside of the thread)
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModuleInfo {
 	pub name: String,
 	pub url: Option<String>,
 	pub developer: String,
 	pub developer_url: Option<String>,
 	pub description: Option<String>,
-	pub icon: Option<String>
+	pub icon: Option<String>,
+	/// the module is sent [CloseLibraryItem](CoreCommands::CloseLibraryItem)
+	/// to close its items, instead of µCore sending SIGTERM to their
+	/// processes
+	#[serde(default)]
+	pub closes_items: bool
 }
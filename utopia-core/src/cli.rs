@@ -0,0 +1,78 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use crate::config::UtopiaConfiguration;
+
+/// Core service of the µtopia game library system
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Args {
+	/// read the configuration from this file instead of
+	/// $XDG_CONFIG_HOME/utopia/core.toml or /etc/utopia/core.toml
+	#[arg(short, long, value_name = "FILE")]
+	pub config: Option<PathBuf>,
+
+	/// path of the socket frontends connect to
+	#[arg(short, long, value_name = "PATH")]
+	pub socket: Option<PathBuf>,
+
+	/// additional directory to load modules from, may be repeated
+	#[arg(short = 'm', long = "module-dir", value_name = "DIR")]
+	pub module_dirs: Vec<PathBuf>,
+
+	/// don't spawn the µtopia database, connect to an already running
+	/// one on the configured socket instead
+	#[arg(long, conflicts_with = "db_url")]
+	pub no_db: bool,
+
+	/// connect to an external redis database instead of spawning one
+	#[arg(long, value_name = "URL")]
+	pub db_url: Option<String>,
+
+	/// stay in the foreground instead of daemonizing
+	#[arg(short, long)]
+	pub foreground: bool,
+
+	/// print the resolved configuration and exit
+	#[arg(long)]
+	pub print_config: bool,
+
+	#[command(subcommand)]
+	pub command: Option<Command>
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+	/// load every module, report the ones that fail (e.g. due to ABI
+	/// mismatches) and exit
+	CheckModules {
+		/// module libraries to check instead of the ones found in the
+		/// module directories
+		files: Vec<PathBuf>
+	}
+}
+
+impl Args {
+	/// loads the configuration and applies the overrides given on the
+	/// command line
+	pub fn configuration(&self) -> Result<UtopiaConfiguration, Box<dyn std::error::Error>> {
+		let mut config = UtopiaConfiguration::load(self.config.as_deref())?;
+		if let Some(socket) = &self.socket {
+			config.socket = socket.clone();
+		}
+		for dir in &self.module_dirs {
+			if !config.modules.search_paths.contains(dir) {
+				config.modules.search_paths.push(dir.clone());
+			}
+		}
+		if self.no_db {
+			config.database.spawn = false;
+		}
+		if let Some(url) = &self.db_url {
+			config.database.url = Some(url.clone());
+			config.database.spawn = false;
+		}
+		Ok(config)
+	}
+}
@@ -0,0 +1,597 @@
+//! µCore configuration
+//!
+//! The configuration is read from `$XDG_CONFIG_HOME/utopia/core.toml`
+//! or, if that does not exist, from `/etc/utopia/core.toml`. Every
+//! value that is not set in the file falls back to a default, and
+//! most values can be overridden by an `UTOPIA_*` environment
+//! variable (see [ENV_OVERRIDES]).
+//!
+//! Paths may contain `~` and `$VAR`/`${VAR}` references, which are
+//! expanded when the configuration is resolved.
+use std::{collections::HashMap,
+          env::var as env_var,
+          path::{Path, PathBuf},
+          time::Duration};
+
+use serde::{de::{DeserializeOwned, IntoDeserializer},
+            Deserialize,
+            Serialize};
+
+use crate::{errors::{ConfigError, FileError},
+            frontend::codec::DEFAULT_MAX_FRAME_SIZE};
+
+/// environment variables that override a configuration key
+///
+/// module search paths are separated by `:`, like `$PATH`, lists of
+/// module names by `,`.
+pub const ENV_OVERRIDES: &[(&str, &str)] = &[
+	("UTOPIA_SOCKET", "socket"),
+	("UTOPIA_DATABASE_TEMPLATE", "database.template"),
+	("UTOPIA_DATABASE_INHERITS", "database.inherits"),
+	("UTOPIA_DATABASE_SOCKET", "database.socket"),
+	("UTOPIA_DATABASE_READY_SOCKET", "database.ready_socket"),
+	("UTOPIA_DATABASE_WORKING_DIR", "database.working_dir"),
+	("UTOPIA_DATABASE_SERVER", "database.server"),
+	("UTOPIA_DATABASE_LOGFILE", "database.logfile"),
+	("UTOPIA_DATABASE_LOGLEVEL", "database.loglevel"),
+	("UTOPIA_DATABASE_SPAWN", "database.spawn"),
+	("UTOPIA_DATABASE_URL", "database.url"),
+	("UTOPIA_MODULE_PATH", "modules.search_paths"),
+	("UTOPIA_MODULES_ENABLE", "modules.enable"),
+	("UTOPIA_MODULES_DISABLE", "modules.disable"),
+	("UTOPIA_MODULES_REQUIRE_MANIFEST", "modules.require_manifest"),
+	("UTOPIA_FRONTEND_MAX_FRAME_SIZE", "frontend.max_frame_size"),
+	("UTOPIA_FRONTEND_DUPLICATE_NAMES", "frontend.duplicate_names"),
+	("UTOPIA_FRONTEND_QUEUE_SIZE", "frontend.queue_size"),
+	("UTOPIA_FRONTEND_WRITE_TIMEOUT", "frontend.write_timeout"),
+	("UTOPIA_FRONTEND_SLOW_CONSUMERS", "frontend.slow_consumers"),
+	("UTOPIA_FRONTEND_PROGRESS_INTERVAL", "frontend.progress_interval"),
+	("UTOPIA_PROCESSES_CLOSE_TIMEOUT", "processes.close_timeout"),
+	("UTOPIA_LOG_FILE", "logging.file")
+];
+
+const REDIS_LOGLEVELS: &[&str] = &["debug", "verbose", "notice", "warning"];
+
+// Raw representation of the configuration file. Everything is
+// optional, as missing values are filled in by defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+	socket: Option<String>,
+	#[serde(default)]
+	database: DatabaseSection,
+	#[serde(default)]
+	modules: ModulesSection,
+	#[serde(default)]
+	frontend: FrontendSection,
+	#[serde(default)]
+	processes: ProcessesSection,
+	#[serde(default)]
+	logging: LoggingSection
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DatabaseSection {
+	template: Option<String>,
+	inherits: Option<String>,
+	socket: Option<String>,
+	ready_socket: Option<String>,
+	working_dir: Option<String>,
+	server: Option<String>,
+	logfile: Option<String>,
+	loglevel: Option<String>,
+	spawn: Option<bool>,
+	url: Option<String>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModulesSection {
+	search_paths: Option<Vec<String>>,
+	enable: Option<Vec<String>>,
+	disable: Option<Vec<String>>,
+	require_manifest: Option<bool>,
+	supervision: Option<SupervisionPolicy>,
+	policies: Option<HashMap<String, SupervisionPolicy>>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FrontendSection {
+	max_frame_size: Option<usize>,
+	duplicate_names: Option<DuplicateNamePolicy>,
+	queue_size: Option<usize>,
+	write_timeout: Option<u64>,
+	slow_consumers: Option<SlowConsumerPolicy>,
+	progress_interval: Option<u64>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProcessesSection {
+	close_timeout: Option<u64>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LoggingSection {
+	file: Option<String>
+}
+
+impl ConfigFile {
+	fn read(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+		let content = std::fs::read_to_string(path).map_err(|io| FileError::new(path.to_owned(), io))?;
+		toml::from_str(&content).map_err(|e| Box::new(ConfigError::new(path.to_string_lossy(), None, e.to_string())).into())
+	}
+
+	fn apply_env(&mut self) -> Result<(), ConfigError> {
+		for (var, key) in ENV_OVERRIDES {
+			let value = match env_var(var) {
+				Ok(value) => value,
+				Err(_) => continue
+			};
+			let slot = match *key {
+				"socket" => &mut self.socket,
+				"database.template" => &mut self.database.template,
+				"database.inherits" => &mut self.database.inherits,
+				"database.socket" => &mut self.database.socket,
+				"database.ready_socket" => &mut self.database.ready_socket,
+				"database.working_dir" => &mut self.database.working_dir,
+				"database.server" => &mut self.database.server,
+				"database.logfile" => &mut self.database.logfile,
+				"database.loglevel" => &mut self.database.loglevel,
+				"database.url" => &mut self.database.url,
+				"logging.file" => &mut self.logging.file,
+				"database.spawn" | "modules.require_manifest" => {
+					let value = Some(value.parse().map_err(|_| {
+						ConfigError::new(
+							format!("environment variable {}", var),
+							Some(key),
+							format!("expected true or false, got \"{}\"", value)
+						)
+					})?);
+					match *key {
+						"database.spawn" => self.database.spawn = value,
+						_ => self.modules.require_manifest = value
+					}
+					continue;
+				},
+				"modules.search_paths" => {
+					self.modules.search_paths = Some(
+						value
+							.split(':')
+							.filter(|p| !p.is_empty())
+							.map(String::from)
+							.collect()
+					);
+					continue;
+				},
+				"frontend.max_frame_size"
+				| "frontend.queue_size"
+				| "frontend.write_timeout"
+				| "frontend.progress_interval"
+				| "processes.close_timeout" => {
+					let value: usize = value.parse().map_err(|_| {
+						ConfigError::new(
+							format!("environment variable {}", var),
+							Some(key),
+							format!("expected a number, got \"{}\"", value)
+						)
+					})?;
+					match *key {
+						"frontend.max_frame_size" => self.frontend.max_frame_size = Some(value),
+						"frontend.queue_size" => self.frontend.queue_size = Some(value),
+						"frontend.write_timeout" => self.frontend.write_timeout = Some(value as u64),
+						"frontend.progress_interval" => self.frontend.progress_interval = Some(value as u64),
+						_ => self.processes.close_timeout = Some(value as u64)
+					}
+					continue;
+				},
+				"frontend.duplicate_names" | "frontend.slow_consumers" => {
+					let invalid = |e: serde::de::value::Error| {
+						ConfigError::new(format!("environment variable {}", var), Some(key), e.to_string())
+					};
+					match *key {
+						"frontend.duplicate_names" => {
+							self.frontend.duplicate_names = Some(parse_variant(&value).map_err(invalid)?)
+						},
+						_ => self.frontend.slow_consumers = Some(parse_variant(&value).map_err(invalid)?)
+					}
+					continue;
+				},
+				"modules.enable" | "modules.disable" => {
+					let names = Some(
+						value
+							.split(',')
+							.map(str::trim)
+							.filter(|n| !n.is_empty())
+							.map(String::from)
+							.collect()
+					);
+					match *key {
+						"modules.enable" => self.modules.enable = names,
+						_ => self.modules.disable = names
+					}
+					continue;
+				},
+				_ => unreachable!("unhandled environment override {}", key)
+			};
+			*slot = Some(value);
+		}
+		Ok(())
+	}
+}
+
+/// parses the name of a unit enum variant, like it's written in the
+/// configuration file
+fn parse_variant<T: DeserializeOwned>(value: &str) -> Result<T, serde::de::value::Error> {
+	T::deserialize(value.into_deserializer())
+}
+
+// Resolves a single value, remembering where it came from in order
+// to produce errors that name the offending key and file.
+struct Resolver {
+	origin: String,
+	home_dir: String
+}
+impl Resolver {
+	fn origin_of(&self, key: &str) -> String {
+		ENV_OVERRIDES
+			.iter()
+			.find(|(var, k)| *k == key && env_var(var).is_ok())
+			.map(|(var, _)| format!("environment variable {}", var))
+			.unwrap_or_else(|| self.origin.clone())
+	}
+
+	fn error(&self, key: &str, msg: impl Into<String>) -> ConfigError {
+		ConfigError::new(self.origin_of(key), Some(key), msg)
+	}
+
+	/// expands `~` and `$VAR`/`${VAR}` within path
+	fn path(&self, key: &str, value: Option<String>, default: impl FnOnce() -> String) -> Result<PathBuf, ConfigError> {
+		let value = value.unwrap_or_else(default);
+		if value.is_empty() {
+			return Err(self.error(key, "path must not be empty"));
+		}
+		let value = match value.strip_prefix('~') {
+			Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("{}{}", self.home_dir, rest),
+			_ => value
+		};
+
+		let mut expanded = String::with_capacity(value.len());
+		let mut chars = value.chars().peekable();
+		while let Some(c) = chars.next() {
+			if c != '$' {
+				expanded.push(c);
+				continue;
+			}
+			let braced = chars.peek() == Some(&'{');
+			if braced {
+				chars.next();
+			}
+			let mut name = String::new();
+			while let Some(&c) = chars.peek() {
+				if c.is_ascii_alphanumeric() || c == '_' {
+					name.push(c);
+					chars.next();
+				} else {
+					break;
+				}
+			}
+			if braced && chars.next() != Some('}') {
+				return Err(self.error(key, format!("unterminated variable reference in \"{}\"", value)));
+			}
+			if name.is_empty() {
+				return Err(self.error(key, format!("empty variable reference in \"{}\"", value)));
+			}
+			match env_var(&name) {
+				Ok(v) => expanded.push_str(&v),
+				Err(_) => return Err(self.error(key, format!("references unset environment variable ${}", name)))
+			}
+		}
+		Ok(PathBuf::from(expanded))
+	}
+}
+
+/// configuration of the µtopia database (a redis server)
+///
+/// the serialized form of this struct is used to render
+/// [template](UtopiaDatabaseConfig::tconfig).
+#[derive(Debug, Serialize)]
+pub struct UtopiaDatabaseConfig {
+	#[serde(rename = "template")]
+	pub tconfig: PathBuf,
+	pub socket: PathBuf,
+	pub inherits: PathBuf,
+	pub logfile: PathBuf,
+	pub loglevel: String,
+	pub working_dir: PathBuf,
+	#[serde(rename = "ready_socket")]
+	pub ready_sock: PathBuf,
+	pub server: PathBuf,
+	/// whether µCore spawns the database itself, or expects it to be
+	/// running already
+	pub spawn: bool,
+	/// redis url of an external database, takes precedence over
+	/// [socket](UtopiaDatabaseConfig::socket)
+	pub url: Option<String>
+}
+impl UtopiaDatabaseConfig {
+	pub fn connection_url(&self) -> String {
+		self.url
+			.clone()
+			.unwrap_or_else(|| format!("unix://{}", self.socket.to_string_lossy()))
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartStrategy {
+	/// restart with an exponentially growing delay
+	Backoff,
+	Never
+}
+
+/// what happens when the thread of a module returns unexpectedly
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SupervisionPolicy {
+	pub restart: RestartStrategy,
+	/// give up after this many failures in a row, 0 never gives up
+	pub max_failures: u32,
+	/// seconds to wait before the first restart
+	pub initial_delay: u64,
+	/// upper bound of the delay in seconds. A module that ran for at
+	/// least this long is considered to have recovered.
+	pub max_delay: u64
+}
+impl Default for SupervisionPolicy {
+	fn default() -> Self {
+		SupervisionPolicy {
+			restart: RestartStrategy::Backoff,
+			max_failures: 5,
+			initial_delay: 1,
+			max_delay: 300
+		}
+	}
+}
+impl SupervisionPolicy {
+	/// delay before restarting after the given number of failures in
+	/// a row
+	pub fn delay(&self, failures: u32) -> Duration {
+		let factor = 2u64.saturating_pow(failures.saturating_sub(1));
+		Duration::from_secs(self.initial_delay.saturating_mul(factor).min(self.max_delay))
+	}
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct UtopiaModulesConfig {
+	/// directories modules are loaded from, in order
+	pub search_paths: Vec<PathBuf>,
+	/// if not empty, only these modules are loaded
+	pub enable: Vec<String>,
+	/// modules that are never loaded
+	pub disable: Vec<String>,
+	/// refuse to load libraries without a module manifest
+	pub require_manifest: bool,
+	pub supervision: SupervisionPolicy,
+	/// supervision policies of single modules, by id
+	pub policies: HashMap<String, SupervisionPolicy>
+}
+impl UtopiaModulesConfig {
+	/// whether a module may be loaded. A module can be referred to by
+	/// its id, the file name of its library or the file name without
+	/// extension, so names should contain all that are known.
+	pub fn is_enabled(&self, names: &[&str]) -> bool {
+		let listed = |list: &Vec<String>| list.iter().any(|entry| names.contains(&entry.as_str()));
+		!listed(&self.disable) && (self.enable.is_empty() || listed(&self.enable))
+	}
+
+	pub fn policy(&self, id: &str) -> &SupervisionPolicy {
+		self.policies.get(id).unwrap_or(&self.supervision)
+	}
+}
+
+/// what happens when a frontend connects with the name of one that is
+/// already connected
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateNamePolicy {
+	/// the new frontend is rejected in the handshake
+	Reject,
+	/// the connection of the old frontend is closed
+	Replace,
+	/// both stay connected, e.g. several instances of the same client
+	Allow
+}
+
+/// what happens when the outbound queue of a frontend is full
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlowConsumerPolicy {
+	/// the frontend is disconnected
+	Disconnect,
+	/// the message is not sent to this frontend
+	DropMessages
+}
+
+#[derive(Debug, Serialize)]
+pub struct UtopiaFrontendConfig {
+	/// largest message in bytes accepted from or sent to a frontend
+	pub max_frame_size: usize,
+	pub duplicate_names: DuplicateNamePolicy,
+	/// messages waiting to be written to a frontend
+	pub queue_size: usize,
+	/// seconds writing a single message may take before the frontend
+	/// is considered dead and disconnected
+	pub write_timeout: u64,
+	pub slow_consumers: SlowConsumerPolicy,
+	/// milliseconds between two progress updates of the same operation
+	/// sent to frontends
+	pub progress_interval: u64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UtopiaProcessesConfig {
+	/// seconds a closing item gets to exit before it is killed
+	pub close_timeout: u64
+}
+
+#[derive(Debug, Serialize)]
+pub struct UtopiaLoggingConfig {
+	/// file stdout and stderr of µCore are appended to
+	pub file: Option<PathBuf>
+}
+
+#[derive(Debug, Serialize)]
+pub struct UtopiaConfiguration {
+	#[serde(skip)]
+	pub runtime_dir: PathBuf,
+	/// configuration file the values were read from, if any
+	#[serde(skip)]
+	pub source: Option<PathBuf>,
+	pub socket: PathBuf,
+	pub database: UtopiaDatabaseConfig,
+	pub modules: UtopiaModulesConfig,
+	pub frontend: UtopiaFrontendConfig,
+	pub processes: UtopiaProcessesConfig,
+	pub logging: UtopiaLoggingConfig
+}
+
+impl UtopiaConfiguration {
+	/// Loads the configuration from path, or if path is None from the
+	/// first existing default location.
+	pub fn load(path: Option<&Path>) -> Result<Self, Box<dyn std::error::Error>> {
+		let home_dir = env_var("HOME").unwrap_or_else(|_| unsafe {
+			let passwd: Option<&libc::passwd> = libc::getpwuid(libc::getuid()).as_ref();
+			passwd
+				.map(|v| std::ffi::CStr::from_ptr(v.pw_dir).to_string_lossy().into_owned())
+				.expect("Failed to get home dir, try setting HOME")
+		});
+
+		let source = match path {
+			Some(path) => Some(path.to_owned()),
+			None => Self::default_locations(&home_dir).into_iter().find(|p| p.exists())
+		};
+		let mut file = match &source {
+			Some(path) => ConfigFile::read(path)?,
+			None => ConfigFile::default()
+		};
+		file.apply_env()?;
+
+		let resolver = Resolver {
+			origin: source
+				.as_ref()
+				.map(|p| p.to_string_lossy().into_owned())
+				.unwrap_or_else(|| String::from("built-in defaults")),
+			home_dir: home_dir.clone()
+		};
+		Ok(Self::resolve(file, &resolver, source)?)
+	}
+
+	fn default_locations(home_dir: &str) -> Vec<PathBuf> {
+		let xdg_config = env_var("XDG_CONFIG_HOME").unwrap_or(format!("{}/.config", home_dir));
+		vec![
+			PathBuf::from(format!("{}/utopia/core.toml", xdg_config)),
+			PathBuf::from("/etc/utopia/core.toml"),
+		]
+	}
+
+	fn resolve(file: ConfigFile, r: &Resolver, source: Option<PathBuf>) -> Result<Self, ConfigError> {
+		let runtime_dir = env_var("XDG_RUNTIME_DIR").map_err(|_| r.error("socket", "XDG_RUNTIME_DIR is not set"))?;
+		let xdg_data = env_var("XDG_DATA_HOME").unwrap_or(format!("{}/.local/share", r.home_dir));
+		let data_dir = format!("{}/utopia", xdg_data);
+
+		let db = file.database;
+		let loglevel = db.loglevel.unwrap_or_else(|| String::from("notice"));
+		if !REDIS_LOGLEVELS.contains(&loglevel.as_str()) {
+			return Err(r.error(
+				"database.loglevel",
+				format!("unknown log level \"{}\", expected one of {:?}", loglevel, REDIS_LOGLEVELS)
+			));
+		}
+		let database = UtopiaDatabaseConfig {
+			tconfig: r.path("database.template", db.template, || {
+				String::from("/usr/share/utopia/db.conf.in")
+			})?,
+			socket: r.path("database.socket", db.socket, || format!("{}/utopiadb.sock", runtime_dir))?,
+			inherits: r.path("database.inherits", db.inherits, || {
+				String::from("/usr/share/utopia/default.conf")
+			})?,
+			logfile: r.path("database.logfile", db.logfile, || format!("{}/db.log", data_dir))?,
+			loglevel,
+			working_dir: r.path("database.working_dir", db.working_dir, || data_dir.clone())?,
+			ready_sock: r.path("database.ready_socket", db.ready_socket, || {
+				format!("{}/_utopiadbctl.dsock", runtime_dir)
+			})?,
+			server: r.path("database.server", db.server, || String::from("/usr/sbin/redis-server"))?,
+			spawn: db.spawn.unwrap_or(db.url.is_none()),
+			url: db.url
+		};
+
+		let mut search_paths = vec![
+			PathBuf::from(format!("{}/modules", data_dir)),
+			PathBuf::from("/usr/lib/utopia/modules"),
+		];
+		for path in file.modules.search_paths.unwrap_or_default() {
+			let path = r.path("modules.search_paths", Some(path), String::new)?;
+			if !search_paths.contains(&path) {
+				search_paths.push(path);
+			}
+		}
+
+		let max_frame_size = file.frontend.max_frame_size.unwrap_or(DEFAULT_MAX_FRAME_SIZE);
+		if max_frame_size == 0 {
+			return Err(r.error("frontend.max_frame_size", "must be greater than 0"));
+		}
+		let queue_size = file.frontend.queue_size.unwrap_or(256);
+		if queue_size == 0 {
+			return Err(r.error("frontend.queue_size", "must be greater than 0"));
+		}
+		let write_timeout = file.frontend.write_timeout.unwrap_or(10);
+		if write_timeout == 0 {
+			return Err(r.error("frontend.write_timeout", "must be greater than 0"));
+		}
+
+		let close_timeout = file.processes.close_timeout.unwrap_or(10);
+		if close_timeout == 0 {
+			return Err(r.error("processes.close_timeout", "must be greater than 0"));
+		}
+
+		let logging = UtopiaLoggingConfig {
+			file: file
+				.logging
+				.file
+				.map(|p| r.path("logging.file", Some(p), String::new))
+				.transpose()?
+		};
+
+		Ok(UtopiaConfiguration {
+			socket: r.path("socket", file.socket, || format!("{}/utopia.sock", runtime_dir))?,
+			database,
+			modules: UtopiaModulesConfig {
+				search_paths,
+				enable: file.modules.enable.unwrap_or_default(),
+				disable: file.modules.disable.unwrap_or_default(),
+				require_manifest: file.modules.require_manifest.unwrap_or(false),
+				supervision: file.modules.supervision.unwrap_or_default(),
+				policies: file.modules.policies.unwrap_or_default()
+			},
+			frontend: UtopiaFrontendConfig {
+				max_frame_size,
+				duplicate_names: file.frontend.duplicate_names.unwrap_or(DuplicateNamePolicy::Reject),
+				queue_size,
+				write_timeout,
+				slow_consumers: file.frontend.slow_consumers.unwrap_or(SlowConsumerPolicy::Disconnect),
+				progress_interval: file.frontend.progress_interval.unwrap_or(500)
+			},
+			processes: UtopiaProcessesConfig {
+				close_timeout
+			},
+			logging,
+			runtime_dir: runtime_dir.into(),
+			source
+		})
+	}
+}
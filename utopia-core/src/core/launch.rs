@@ -0,0 +1,110 @@
+//! Launch options of items, edited through the preference dialogs
+//! µCore offers as [CORE_ID](utopia_common::frontend::CORE_ID)
+use std::collections::HashMap;
+
+use utopia_common::library::{preferences::{DiagType, FieldType, InputField, PreferenceDiag, PreferenceGroup, PreferencePane},
+                             LaunchOptions};
+
+use super::Library;
+use crate::errors::InvalidPreferenceError;
+
+const DIAG_ID: &str = "launch_options";
+const ENV: &str = "env";
+const ARGS: &str = "args";
+const WRAPPER: &str = "wrapper";
+
+fn diag(options: &LaunchOptions, description: &str) -> PreferenceDiag {
+	let field = |uuid: &str, title: &str, subtitle: &str, r#type| InputField {
+		uuid: String::from(uuid),
+		title: String::from(title),
+		subtitle: Some(String::from(subtitle)),
+		r#type
+	};
+	PreferenceDiag {
+		uuid: String::from(DIAG_ID),
+		panes: vec![PreferencePane {
+			title: String::from("Launch"),
+			icon: None,
+			groups: vec![PreferenceGroup {
+				title: String::from("Launch options"),
+				description: Some(String::from(description)),
+				fields: vec![
+					field(ENV, "Environment variables", "e.g. MANGOHUD = 1", FieldType::KeyValueList(options.env.clone())),
+					field(ARGS, "Arguments", "appended to the command line", FieldType::List(options.args.clone())),
+					field(
+						WRAPPER,
+						"Wrapper command",
+						"the item is started through, e.g. gamemoderun",
+						FieldType::List(options.wrapper.clone())
+					),
+				]
+			}]
+		}]
+	}
+}
+
+/// applies the fields of [diag] in values to options. Fields that
+/// aren't in values stay as they are, and none change if one is
+/// invalid.
+fn apply(options: &mut LaunchOptions, values: HashMap<String, FieldType>) -> Result<(), InvalidPreferenceError> {
+	let mut updated = options.clone();
+	for (field, value) in values {
+		let invalid = |reason: &str| InvalidPreferenceError::new(&field, reason);
+		match field.as_str() {
+			ENV => {
+				let env = value.try_get_kvlist().ok_or_else(|| invalid("expected a key value list"))?;
+				if env.keys().any(|key| key.is_empty() || key.contains(['=', '\0'])) {
+					return Err(invalid("variable names can't be empty or contain = or NUL"));
+				}
+				updated.env = env;
+			},
+			ARGS | WRAPPER => {
+				let list = value
+					.try_get_list()
+					.ok_or_else(|| invalid("expected a list"))?
+					.into_iter()
+					.filter(|entry| !entry.is_empty())
+					.collect();
+				match field.as_str() {
+					ARGS => updated.args = list,
+					_ => updated.wrapper = list
+				}
+			},
+			_ => return Err(invalid("unknown field"))
+		}
+	}
+	*options = updated;
+	Ok(())
+}
+
+impl Library {
+	/// preference dialog of the launch options of an item, or of every
+	/// item for [DiagType::Module]
+	pub fn launch_diag(&self, kind: &DiagType) -> Result<PreferenceDiag, Box<dyn std::error::Error>> {
+		Ok(match kind {
+			DiagType::Item(uuid) => diag(
+				&self.get(uuid)?.launch,
+				"Added to the options of every item, variables set here win"
+			),
+			DiagType::Module => diag(&self.launch, "Applied to every item")
+		})
+	}
+
+	pub fn update_launch_options(
+		&mut self,
+		kind: &DiagType,
+		values: HashMap<String, FieldType>
+	) -> Result<(), Box<dyn std::error::Error>> {
+		match kind {
+			DiagType::Item(uuid) => {
+				apply(&mut self.get_mut(uuid)?.launch, values)?;
+				self.persist(uuid);
+			},
+			DiagType::Module => {
+				apply(&mut self.launch, values)?;
+				self.store.save_launch_options(&self.launch)?;
+			}
+		}
+		Ok(())
+	}
+}
@@ -1,12 +1,34 @@
 //use utopia_module::com::{library, CoreCommands};
-use std::collections::HashMap;
+mod launch;
+pub mod operations;
+mod query;
+pub mod store;
+
+use std::{collections::HashMap,
+          time::{SystemTime, UNIX_EPOCH}};
 
 use utopia_common::{library, module::CoreCommands};
 use futures::stream::FuturesUnordered;
 use tokio::task::JoinHandle;
 
-use crate::{errors::{LibraryItemNotAvailableError, ModuleNotAvailableError, ProvModuleNotAvailableError},
-            modules::modules::ModuleManager};
+use crate::{config::UtopiaProcessesConfig,
+            errors::{InvalidInstallDirError,
+                     ItemNotRunningError,
+                     ItemStatusError,
+                     LibraryItemNotAvailableError,
+                     ModuleNotAvailableError,
+                     ModuleUnloadError,
+                     ProcessNotTrackedError,
+                     ProvModuleNotAvailableError},
+            modules::modules::{intern, ModuleManager},
+            process::tree::Tree};
+
+/// how an item of the library changed, by uuid
+#[derive(Debug, Clone, PartialEq)]
+pub enum ItemChange {
+	Updated(String),
+	Removed(String)
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum UpdStateAction {
@@ -19,8 +41,88 @@ pub struct ItemProvider {
 	pub title: String,
 	pub icon: Option<String>,
 	pub status: Vec<library::LibraryItemStatus>,
+	/// restored from the database or its module is gone, but it may
+	/// come back
+	pub stale: bool,
 	module: &'static str
 }
+impl ItemProvider {
+	fn pids(&self) -> Vec<u32> {
+		self.status
+			.iter()
+			.filter_map(|status| match status {
+				library::LibraryItemStatus::Running(pid) => *pid,
+				_ => None
+			})
+			.collect()
+	}
+
+	fn running(&self) -> bool {
+		self.status
+			.iter()
+			.any(|status| matches!(status, library::LibraryItemStatus::Running(_)))
+	}
+
+	/// fails unless the item is installed via the provider and neither
+	/// running nor being installed or updated, so it can be changed
+	fn check_idle(&self, uuid: &str, name: &str, action: &'static str) -> Result<(), ItemStatusError> {
+		let reason = if self.running() || self.status.contains(&library::LibraryItemStatus::Closing) {
+			"it is running"
+		} else if self.status.contains(&library::LibraryItemStatus::NotInstalled) {
+			"it isn't installed"
+		} else if self.status.contains(&library::LibraryItemStatus::Installing) {
+			"it is being installed"
+		} else if self.status.contains(&library::LibraryItemStatus::Updating) {
+			"it is being updated"
+		} else {
+			return Ok(());
+		};
+		Err(ItemStatusError::new(uuid, name, action, reason))
+	}
+
+	/// fails if the item is installed via the provider already, or
+	/// being installed
+	fn check_installable(&self, uuid: &str, name: &str) -> Result<(), ItemStatusError> {
+		let installed = self.running()
+			|| self.status.iter().any(|status| {
+				matches!(
+					status,
+					library::LibraryItemStatus::Installed
+						| library::LibraryItemStatus::Updatable
+						| library::LibraryItemStatus::Updating
+						| library::LibraryItemStatus::Closing
+				)
+			});
+		let reason = if installed {
+			"it is installed already"
+		} else if self.status.contains(&library::LibraryItemStatus::Installing) {
+			"it is being installed"
+		} else {
+			return Ok(());
+		};
+		Err(ItemStatusError::new(uuid, name, "install", reason))
+	}
+
+	fn to_frontend(&self, uuid: &str) -> library::LibraryProvider {
+		library::LibraryProvider::new(
+			uuid.to_owned(),
+			self.title.clone(),
+			self.icon.clone(),
+			self.status.clone(),
+			self.stale
+		)
+	}
+}
+
+/// whether status is still true after µCore restarted
+fn persists(status: &library::LibraryItemStatus) -> bool {
+	matches!(
+		status,
+		library::LibraryItemStatus::Installed
+			| library::LibraryItemStatus::Updatable
+			| library::LibraryItemStatus::NotInstalled
+	)
+}
 
 pub struct LibraryItem {
 	uuid: String,
@@ -28,7 +130,16 @@ pub struct LibraryItem {
 	kind: library::LibraryItemKind,
 	pub details: library::LibraryItemDetails,
 	active_provider: (String, ItemProvider),
-	providers: HashMap<String, ItemProvider>
+	providers: HashMap<String, ItemProvider>,
+	/// unix timestamp of the last launch
+	last_played: Option<u64>,
+	/// seconds the item ran in total, not counting running processes
+	playtime: u64,
+	/// launch time of running processes, by pid
+	sessions: HashMap<u32, SystemTime>,
+	last_crash: Option<library::Crash>,
+	/// set by the user, on top of the ones of every item
+	launch: library::LaunchOptions
 }
 impl LibraryItem {
 	pub fn new(provider: &'static str, title: String, icon: Option<String>, item: library::LibraryItemModule) -> Self {
@@ -36,6 +147,7 @@ impl LibraryItem {
 			title,
 			icon,
 			status: item.status,
+			stale: false,
 			module: provider
 		};
 		let mut providers = HashMap::new();
@@ -46,88 +158,350 @@ impl LibraryItem {
 			kind: item.kind,
 			details: item.details,
 			active_provider: (String::from(provider), iprovider),
-			providers
+			providers,
+			last_played: None,
+			playtime: 0,
+			sessions: HashMap::new(),
+			last_crash: None,
+			launch: library::LaunchOptions::default()
 		}
 	}
 
-	pub fn add_provider(
-		&mut self,
-		provider: &'static str,
-		title: String,
-		icon: Option<String>,
-		status: Vec<library::LibraryItemStatus>
-	) {
+	/// restores an item from the database. Its providers are stale
+	/// until their modules add the item again.
+	pub fn from_stored(item: store::StoredItem) -> Option<Self> {
+		let providers: HashMap<String, ItemProvider> = item
+			.providers
+			.into_iter()
+			.map(|(module, provider)| {
+				let iprovider = ItemProvider {
+					title: provider.title,
+					icon: provider.icon,
+					status: provider.status.into_iter().filter(persists).collect(),
+					stale: true,
+					module: intern(&module)
+				};
+				(module, iprovider)
+			})
+			.collect();
+		let active_provider = match providers.get_key_value(&item.active_provider) {
+			Some((module, provider)) => (module.clone(), provider.clone()),
+			None => providers.iter().next().map(|(module, provider)| (module.clone(), provider.clone()))?
+		};
+		Some(LibraryItem {
+			uuid: item.uuid,
+			name: item.name,
+			kind: item.kind,
+			details: item.details,
+			active_provider,
+			providers,
+			last_played: item.last_played,
+			playtime: item.playtime,
+			sessions: HashMap::new(),
+			last_crash: item.last_crash,
+			launch: item.launch
+		})
+	}
+
+	pub fn to_stored(&self) -> store::StoredItem {
+		store::StoredItem {
+			uuid: self.uuid.clone(),
+			name: self.name.clone(),
+			kind: self.kind,
+			details: self.details.clone(),
+			active_provider: self.active_provider.0.clone(),
+			providers: self
+				.providers
+				.iter()
+				.map(|(module, provider)| {
+					(module.clone(), store::StoredProvider {
+						title: provider.title.clone(),
+						icon: provider.icon.clone(),
+						// interrupted updates and installs have to be started
+						// again
+						status: provider
+							.status
+							.iter()
+							.map(|status| match status {
+								library::LibraryItemStatus::Updating => library::LibraryItemStatus::Updatable,
+								library::LibraryItemStatus::Installing => library::LibraryItemStatus::NotInstalled,
+								status => *status
+							})
+							.filter(persists)
+							.collect()
+					})
+				})
+				.collect(),
+			last_played: self.last_played,
+			playtime: self.playtime,
+			last_crash: self.last_crash.clone(),
+			launch: self.launch.clone()
+		}
+	}
+
+	/// the active provider is a copy, that has to be updated with its
+	/// original
+	fn sync_active_provider(&mut self) {
+		if let Some(provider) = self.providers.get(&self.active_provider.0) {
+			self.active_provider.1 = provider.clone();
+		}
+	}
+
+	/// adds provider to the item, or confirms it if it's already known
+	///
+	/// the active provider has the final say on the item's metadata.
+	pub fn add_provider(&mut self, provider: &'static str, title: String, icon: Option<String>, item: library::LibraryItemModule) {
+		let mut status = item.status;
+		// processes launched before the module was loaded again are
+		// still tracked by µCore
+		if let Some(known) = self.providers.get(provider) {
+			let tracked = known
+				.pids()
+				.into_iter()
+				.map(|pid| library::LibraryItemStatus::Running(Some(pid)))
+				.filter(|running| !status.contains(running))
+				.collect::<Vec<_>>();
+			status.extend(tracked);
+		}
 		self.providers.insert(String::from(provider), ItemProvider {
 			title,
 			icon,
 			status,
+			stale: false,
 			module: provider
 		});
+		if self.active_provider.0 == provider {
+			self.name = item.name;
+			self.kind = item.kind;
+			self.details = item.details;
+		}
+		self.sync_active_provider();
+	}
+
+	/// marks provider as stale, returns whether the item has it
+	pub fn mark_stale(&mut self, provider: &str) -> bool {
+		match self.providers.get_mut(provider) {
+			Some(prov) => {
+				prov.stale = true;
+				self.sync_active_provider();
+				true
+			},
+			None => false
+		}
+	}
+
+	/// records the launch of a process of the item
+	pub fn started(&mut self, pid: u32) {
+		let now = SystemTime::now();
+		self.last_played = now.duration_since(UNIX_EPOCH).ok().map(|since| since.as_secs());
+		self.sessions.insert(pid, now);
+	}
+
+	/// adds the time the process ran to the playtime of the item
+	pub fn stopped(&mut self, pid: u32) {
+		if let Some(started) = self.sessions.remove(&pid) {
+			self.playtime += started.elapsed().map_or(0, |ran| ran.as_secs());
+		}
 	}
 
-	pub fn run_default(&self, mod_mgr: &ModuleManager) -> Result<(), Box<dyn std::error::Error>> {
+	/// removes the running state of a process of provider that exited,
+	/// and the closing one unless other processes are still closing.
+	/// Processes without a pid are closed by the module, which reports
+	/// when they are.
+	pub fn exited(&mut self, provider: &str, pid: u32, closing_left: bool) -> Result<(), ProvModuleNotAvailableError> {
+		let prov = self
+			.providers
+			.get_mut(provider)
+			.ok_or_else(|| ProvModuleNotAvailableError::new(provider.to_string()))?;
+		prov.status.retain(|status| *status != library::LibraryItemStatus::Running(Some(pid)));
+		if !closing_left && !prov.status.contains(&library::LibraryItemStatus::Running(None)) {
+			prov.status.retain(|status| *status != library::LibraryItemStatus::Closing);
+		}
+		self.sync_active_provider();
+		self.stopped(pid);
+		Ok(())
+	}
+
+	/// removes the running state of processes without a pid, which
+	/// provider reported to have exited. Processes µCore tracks are
+	/// left to their watchers, the item stays closing until they exited.
+	pub fn closed(&mut self, provider: &str) -> Result<(), ProvModuleNotAvailableError> {
+		let prov = self
+			.providers
+			.get_mut(provider)
+			.ok_or_else(|| ProvModuleNotAvailableError::new(provider.to_string()))?;
+		prov.status.retain(|status| *status != library::LibraryItemStatus::Running(None));
+		if prov.pids().is_empty() {
+			prov.status.retain(|status| *status != library::LibraryItemStatus::Closing);
+		}
+		self.sync_active_provider();
+		Ok(())
+	}
+
+	/// removes the closing state of provider once the close timeout is
+	/// over, if only processes without a pid are left. Nothing else
+	/// would, as µCore can't kill them. Returns whether it did.
+	pub fn close_expired(&mut self, provider: &str) -> Result<bool, ProvModuleNotAvailableError> {
+		let prov = self
+			.providers
+			.get_mut(provider)
+			.ok_or_else(|| ProvModuleNotAvailableError::new(provider.to_string()))?;
+		if !prov.status.contains(&library::LibraryItemStatus::Closing) || !prov.pids().is_empty() {
+			return Ok(false);
+		}
+		prov.status.retain(|status| *status != library::LibraryItemStatus::Closing);
+		self.sync_active_provider();
+		Ok(true)
+	}
+
+	/// records a crash of the item while provider ran it
+	pub fn crashed(&mut self, provider: &str, info: library::CrashInfo) -> library::Crash {
+		let crash = library::Crash {
+			time: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()),
+			provider: provider.to_string(),
+			info
+		};
+		self.last_crash = Some(crash.clone());
+		crash
+	}
+
+	/// launches the item with global merged with its launch options
+	pub fn run_default(&self, mod_mgr: &ModuleManager, global: &library::LaunchOptions) -> Result<(), Box<dyn std::error::Error>> {
 		mod_mgr
-			.get(&self.active_provider.1.module)?
-			.send(CoreCommands::LaunchLibraryItem(self.uuid.clone()))?;
+			.get(self.active_provider.1.module)?
+			.send(CoreCommands::LaunchLibraryItem(self.uuid.clone(), global.merged(&self.launch)))?;
 		Ok(())
 	}
 
-	pub fn run_provider(&self, mod_mgr: &ModuleManager, provider: String) -> Result<(), Box<dyn std::error::Error>> {
+	pub fn run_provider(
+		&self,
+		mod_mgr: &ModuleManager,
+		provider: String,
+		global: &library::LaunchOptions
+	) -> Result<(), Box<dyn std::error::Error>> {
 		match self.providers.get(&provider) {
 			Some(provider) => {
 				mod_mgr
 					.get(provider.module)?
-					.send(CoreCommands::LaunchLibraryItem(self.uuid.clone()))?;
+					.send(CoreCommands::LaunchLibraryItem(self.uuid.clone(), global.merged(&self.launch)))?;
 				Ok(())
 			},
 			None => Err(Box::new(ProvModuleNotAvailableError::new(provider)))
 		}
 	}
 
-	pub fn close_default(&self, mod_mgr: &ModuleManager) -> Result<(), Box<dyn std::error::Error>> {
+	/// module of provider, the active one if None, and the pids it runs
+	/// the item with. Fails if provider doesn't run the item.
+	pub fn running_provider(&self, provider: Option<String>) -> Result<(&'static str, Vec<u32>), Box<dyn std::error::Error>> {
+		let name = provider.unwrap_or_else(|| self.active_provider.0.clone());
+		match self.providers.get(&name) {
+			Some(prov) if prov.running() => Ok((prov.module, prov.pids())),
+			Some(_) => Err(Box::new(ItemNotRunningError::new(&self.uuid, &name))),
+			None => Err(Box::new(ProvModuleNotAvailableError::new(name)))
+		}
+	}
+
+	/// whether provider runs the item as other processes than pid
+	pub fn runs_besides(&self, provider: &str, pid: u32) -> bool {
+		self.providers.get(provider).is_some_and(|prov| {
+			prov.status
+				.iter()
+				.any(|status| matches!(status, library::LibraryItemStatus::Running(other) if *other != Some(pid)))
+		})
+	}
+
+	pub fn update_default(&self, mod_mgr: &ModuleManager) -> Result<(), Box<dyn std::error::Error>> {
+		self.active_provider.1.check_idle(&self.uuid, &self.active_provider.0, "update")?;
 		mod_mgr
-			.get(&self.active_provider.1.module)?
-			.send(CoreCommands::CloseLibraryItem(self.uuid.clone()))?;
+			.get(self.active_provider.1.module)?
+			.send(CoreCommands::UpdateLibraryItem(self.uuid.clone()))?;
 		Ok(())
 	}
 
-	pub fn close_provider(&self, mod_mgr: &ModuleManager, provider: String) -> Result<(), Box<dyn std::error::Error>> {
-		match self.providers.get(&provider) {
-			Some(provider) => {
+	pub fn uninstall_default(&self, mod_mgr: &ModuleManager) -> Result<(), Box<dyn std::error::Error>> {
+		self.active_provider.1.check_idle(&self.uuid, &self.active_provider.0, "uninstall")?;
+		mod_mgr
+			.get(self.active_provider.1.module)?
+			.send(CoreCommands::UninstallLibraryItem(self.uuid.clone()))?;
+		Ok(())
+	}
+
+	pub fn install_provider(
+		&self,
+		mod_mgr: &ModuleManager,
+		provider: &str,
+		options: library::InstallOptions
+	) -> Result<(), Box<dyn std::error::Error>> {
+		match self.providers.get(provider) {
+			Some(prov) => {
+				prov.check_installable(&self.uuid, provider)?;
 				mod_mgr
-					.get(provider.module)?
-					.send(CoreCommands::CloseLibraryItem(self.uuid.clone()))?;
+					.get(prov.module)?
+					.send(CoreCommands::InstallLibraryItem(self.uuid.clone(), options))?;
 				Ok(())
 			},
-			None => Err(Box::new(ProvModuleNotAvailableError::new(provider)))
+			None => Err(Box::new(ProvModuleNotAvailableError::new(provider.to_string())))
 		}
 	}
 
-	pub fn kill_default(&self) {
-		let _: Vec<()> = self.active_provider.1.status.iter().map(|status| unsafe {
-			if let library::LibraryItemStatus::Running(pid) = status {
-				if let Some(pid) = pid {
-					libc::kill(*pid as i32, libc::SIGKILL);
-				}
-			}
-		}).collect();
+	/// pids of the processes the active provider runs
+	pub fn pids_default(&self) -> Vec<u32> {
+		self.active_provider.1.pids()
 	}
-	pub fn kill_provider(&self, provider: String) -> Result<(), ProvModuleNotAvailableError> {
+
+	pub fn pids_provider(&self, provider: String) -> Result<Vec<u32>, ProvModuleNotAvailableError> {
 		match self.providers.get(&provider) {
-			Some(provider) => {
-				let _: Vec<()> = provider.status.iter().map(|status| unsafe {
-					if let library::LibraryItemStatus::Running(pid) = status {
-						if let Some(pid) = pid {
-							libc::kill(*pid as i32, libc::SIGKILL);
-						}
-					}
-				}).collect();
-				Ok(())
-			},
+			Some(provider) => Ok(provider.pids()),
 			None => Err(ProvModuleNotAvailableError::new(provider))
 		}
 	}
 
+	/// removes provider from the item, making another one active if
+	/// necessary. Returns false if the item has no providers left.
+	pub fn remove_provider(&mut self, provider: &str) -> bool {
+		self.providers.remove(provider);
+		if self.active_provider.0 == provider {
+			match self.providers.iter().next() {
+				Some((name, prov)) => self.active_provider = (name.clone(), prov.clone()),
+				None => return false
+			}
+		}
+		true
+	}
+
+	/// applies a patch of provider to the item
+	///
+	/// status replaces the one of the provider, except for running
+	/// processes, which are tracked by µCore. Name and details are only
+	/// taken from the active provider.
+	pub fn apply_patch(&mut self, provider: &str, patch: library::LibraryItemPatch) -> Result<(), ProvModuleNotAvailableError> {
+		let prov = self
+			.providers
+			.get_mut(provider)
+			.ok_or_else(|| ProvModuleNotAvailableError::new(provider.to_string()))?;
+		if let Some(mut status) = patch.status {
+			let running = prov
+				.status
+				.iter()
+				.filter(|status| matches!(status, library::LibraryItemStatus::Running(_)))
+				.filter(|running| !status.contains(running))
+				.copied()
+				.collect::<Vec<_>>();
+			status.extend(running);
+			prov.status = status;
+		}
+		if self.active_provider.0 == provider {
+			if let Some(name) = patch.name {
+				self.name = name;
+			}
+			if let Some(details) = patch.details {
+				self.details = details;
+			}
+		}
+		self.sync_active_provider();
+		Ok(())
+	}
+
 	pub fn change_default_provider(&mut self, provider: String) -> Result<(), ProvModuleNotAvailableError> {
 		let title = self
 			.providers
@@ -143,16 +517,14 @@ impl LibraryItem {
 		action: UpdStateAction,
 		state: library::LibraryItemStatus
 	) -> Result<(), ProvModuleNotAvailableError> {
-		let mut prov = self
+		let prov = self
 			.providers
 			.get_mut(&provider)
 			.ok_or(ProvModuleNotAvailableError::new(provider.clone()))?;
 		if action == UpdStateAction::Remove {
 			prov.status = prov.status.clone().into_iter().filter(|&i| i != state).collect();
-		} else if action == UpdStateAction::Add {
-			if !prov.status.iter().any(|&i| i == state) {
-				prov.status.push(state)
-			}
+		} else if action == UpdStateAction::Add && !prov.status.contains(&state) {
+			prov.status.push(state)
 		}
 		if self.active_provider.0 == provider {
 			self.active_provider.1 = prov.clone();
@@ -160,32 +532,39 @@ impl LibraryItem {
 		Ok(())
 	}
 
+	/// drops every status in remove from provider, then adds add
+	pub fn replace_state(
+		&mut self,
+		provider: &str,
+		remove: &[library::LibraryItemStatus],
+		add: Option<library::LibraryItemStatus>
+	) -> Result<(), ProvModuleNotAvailableError> {
+		let prov = self
+			.providers
+			.get_mut(provider)
+			.ok_or_else(|| ProvModuleNotAvailableError::new(provider.to_string()))?;
+		prov.status.retain(|status| !remove.contains(status));
+		if let Some(state) = add.filter(|state| !prov.status.contains(state)) {
+			prov.status.push(state);
+		}
+		self.sync_active_provider();
+		Ok(())
+	}
+
 	pub fn to_frontend(&self) -> library::LibraryItemFrontend {
 		library::LibraryItemFrontend {
 			uuid: self.uuid.clone(),
 			name: self.name.clone(),
 			kind: self.kind,
-			active_provider: library::LibraryProvider::new(
-				self.active_provider.0.clone(),
-				self.active_provider.1.title.clone(),
-				self.active_provider.1.icon.clone(),
-				self.active_provider.1.status.clone()
-			),
+			active_provider: self.active_provider.1.to_frontend(&self.active_provider.0),
 			providers: self
 				.providers
 				.iter()
-				.map(|(k, v)| {
-					(
-						k.to_owned(),
-						(library::LibraryProvider::new(
-							k.to_owned(),
-							v.title.clone(),
-							v.icon.clone(),
-							v.status.clone()
-						))
-					)
-				})
-				.collect()
+				.map(|(k, v)| (k.to_owned(), v.to_frontend(k)))
+				.collect(),
+			last_played: self.last_played,
+			playtime: self.playtime,
+			last_crash: self.last_crash.clone()
 		}
 	}
 
@@ -195,38 +574,56 @@ impl LibraryItem {
 			name: self.name.clone(),
 			kind: self.kind,
 			details: self.details.clone(),
-			active_provider: library::LibraryProvider::new(
-				self.active_provider.0.clone(),
-				self.active_provider.1.title.clone(),
-				self.active_provider.1.icon.clone(),
-				self.active_provider.1.status.clone()
-			),
+			active_provider: self.active_provider.1.to_frontend(&self.active_provider.0),
 			providers: self
 				.providers
 				.iter()
-				.map(|(k, v)| {
-					(
-						k.to_owned(),
-						(library::LibraryProvider::new(
-							k.to_owned(),
-							v.title.clone(),
-							v.icon.clone(),
-							v.status.clone()
-						))
-					)
-				})
+				.map(|(k, v)| (k.to_owned(), v.to_frontend(k)))
 				.collect()
 		}
 	}
 }
 
 pub struct Library {
-	inner: HashMap<String, LibraryItem>
+	inner: HashMap<String, LibraryItem>,
+	/// launch options of every item
+	launch: library::LaunchOptions,
+	store: store::LibraryStore
 }
 impl Library {
-	pub fn new() -> Self {
+	/// restores the library that was persisted in store
+	pub fn new(mut store: store::LibraryStore) -> Self {
+		let inner = match store.load() {
+			Ok(items) => items
+				.into_iter()
+				.filter_map(LibraryItem::from_stored)
+				.map(|item| (item.uuid.clone(), item))
+				.collect(),
+			Err(e) => {
+				eprintln!("Failed restoring library from database: {}", e);
+				HashMap::new()
+			}
+		};
+		println!("Restored {} library items", inner.len());
+		let launch = store.load_launch_options().unwrap_or_else(|e| {
+			eprintln!("Failed restoring launch options from database: {}", e);
+			library::LaunchOptions::default()
+		});
 		Library {
-			inner: HashMap::new()
+			inner,
+			launch,
+			store
+		}
+	}
+
+	/// writes the current state of the item to the database
+	fn persist(&mut self, uuid: &str) {
+		let res = match self.inner.get(uuid) {
+			Some(item) => self.store.save(&item.to_stored()),
+			None => self.store.remove(uuid).map_err(|e| e.into())
+		};
+		if let Err(e) = res {
+			eprintln!("Failed persisting library item {}: {}", uuid, e);
 		}
 	}
 
@@ -236,18 +633,17 @@ impl Library {
 		item: library::LibraryItemModule,
 		mod_mgr: &ModuleManager
 	) -> Result<(), ModuleNotAvailableError> {
-		let modinfo = mod_mgr.get(&module)?.module.get_module_info();
-		let status = item.status.clone();
-		println!("Added {}", item.uuid.clone());
-		self.inner
-			.entry(item.uuid.clone())
-			.and_modify(|item| item.add_provider(module, modinfo.name.clone(), modinfo.icon.clone(), status))
-			.or_insert(LibraryItem::new(
-				module,
-				modinfo.name.clone(),
-				modinfo.icon.clone(),
-				item
-			));
+		let modinfo = mod_mgr.get(module)?.module.get_module_info();
+		let uuid = item.uuid.clone();
+		println!("Added {}", uuid);
+		match self.inner.get_mut(&uuid) {
+			Some(known) => known.add_provider(module, modinfo.name, modinfo.icon, item),
+			None => {
+				self.inner
+					.insert(uuid.clone(), LibraryItem::new(module, modinfo.name, modinfo.icon, item));
+			}
+		}
+		self.persist(&uuid);
 		Ok(())
 	}
 
@@ -277,12 +673,72 @@ impl Library {
 		}
 	}
 
+	/// records the launch of a process module runs the item as
+	pub fn started(&mut self, module: &str, uuid: &String, pid: u32) -> Result<(), Box<dyn std::error::Error>> {
+		let item = self.get_mut(uuid)?;
+		item.update_state(
+			module.to_string(),
+			UpdStateAction::Add,
+			library::LibraryItemStatus::Running(Some(pid))
+		)?;
+		item.started(pid);
+		self.persist(uuid);
+		Ok(())
+	}
+
+	/// records that a process of the item exited
+	pub fn stopped(&mut self, uuid: &String, pid: u32) -> Result<(), LibraryItemNotAvailableError> {
+		self.get_mut(uuid)?.stopped(pid);
+		self.persist(uuid);
+		Ok(())
+	}
+
+	/// a process module ran the item with exited, see [LibraryItem::exited]
+	pub fn exited(
+		&mut self,
+		module: &str,
+		uuid: &String,
+		pid: u32,
+		closing_left: bool
+	) -> Result<(), Box<dyn std::error::Error>> {
+		self.get_mut(uuid)?.exited(module, pid, closing_left)?;
+		self.persist(uuid);
+		Ok(())
+	}
+
+	/// module reported that the item exited, see [LibraryItem::closed]
+	pub fn closed(&mut self, module: &str, uuid: &String) -> Result<(), Box<dyn std::error::Error>> {
+		self.get_mut(uuid)?.closed(module)?;
+		self.persist(uuid);
+		Ok(())
+	}
+
+	/// see [LibraryItem::close_expired]
+	pub fn close_expired(&mut self, module: &str, uuid: &String) -> Result<bool, Box<dyn std::error::Error>> {
+		let expired = self.get_mut(uuid)?.close_expired(module)?;
+		if expired {
+			self.persist(uuid);
+		}
+		Ok(expired)
+	}
+
+	pub fn crashed(
+		&mut self,
+		module: &str,
+		uuid: &String,
+		info: library::CrashInfo
+	) -> Result<library::Crash, LibraryItemNotAvailableError> {
+		let crash = self.get_mut(uuid)?.crashed(module, info);
+		self.persist(uuid);
+		Ok(crash)
+	}
+
 	pub fn launch_library_item(
 		&self,
 		uuid: &String,
 		mod_mgr: &ModuleManager
 	) -> Result<(), Box<dyn std::error::Error>> {
-		self.get(uuid)?.run_default(mod_mgr)?;
+		self.get(uuid)?.run_default(mod_mgr, &self.launch)?;
 		Ok(())
 	}
 
@@ -292,24 +748,162 @@ impl Library {
 		mod_mgr: &ModuleManager,
 		provider: String
 	) -> Result<(), Box<dyn std::error::Error>> {
-		self.get(uuid)?.run_provider(mod_mgr, provider)?;
+		self.get(uuid)?.run_provider(mod_mgr, provider, &self.launch)?;
 		Ok(())
 	}
 
-	pub fn kill_provider(&self, uuid: &String, provider: String) -> Result<(), Box<dyn std::error::Error>> {
-		self.get(uuid)?.kill_provider(provider)?;
+	/// asks the active provider to update the item, which is Updating
+	/// until it reports back. Returns the id of that provider's module.
+	pub fn update_library_item(
+		&mut self,
+		uuid: &String,
+		mod_mgr: &ModuleManager
+	) -> Result<&'static str, Box<dyn std::error::Error>> {
+		let item = self.get_mut(uuid)?;
+		item.update_default(mod_mgr)?;
+		let module = item.active_provider.1.module;
+		item.replace_state(
+			module,
+			&[library::LibraryItemStatus::Updatable],
+			Some(library::LibraryItemStatus::Updating)
+		)?;
+		self.persist(uuid);
+		Ok(module)
+	}
+
+	pub fn uninstall_library_item(&self, uuid: &String, mod_mgr: &ModuleManager) -> Result<(), Box<dyn std::error::Error>> {
+		self.get(uuid)?.uninstall_default(mod_mgr)?;
+		Ok(())
+	}
+
+	/// asks provider to install the item, which is Installing until it
+	/// reports back
+	pub fn install_library_item(
+		&mut self,
+		uuid: &String,
+		provider: &str,
+		options: library::InstallOptions,
+		mod_mgr: &ModuleManager
+	) -> Result<(), Box<dyn std::error::Error>> {
+		if let Some(dir) = options.target_dir.as_ref().filter(|dir| !dir.is_absolute()) {
+			return Err(Box::new(InvalidInstallDirError::new(dir.clone())));
+		}
+		let item = self.get_mut(uuid)?;
+		item.install_provider(mod_mgr, provider, options)?;
+		item.replace_state(
+			provider,
+			&[library::LibraryItemStatus::NotInstalled],
+			Some(library::LibraryItemStatus::Installing)
+		)?;
+		self.persist(uuid);
+		Ok(())
+	}
+
+	/// replaces status of module on the item, see
+	/// [replace_state](LibraryItem::replace_state)
+	pub fn replace_state(
+		&mut self,
+		module: &str,
+		uuid: &String,
+		remove: &[library::LibraryItemStatus],
+		add: Option<library::LibraryItemStatus>
+	) -> Result<(), Box<dyn std::error::Error>> {
+		self.get_mut(uuid)?.replace_state(module, remove, add)?;
+		self.persist(uuid);
 		Ok(())
 	}
 
+	/// module and pids of the provider to close the item of, see
+	/// [LibraryItem::running_provider]
+	pub fn running_provider(
+		&self,
+		uuid: &String,
+		provider: Option<String>
+	) -> Result<(&'static str, Vec<u32>), Box<dyn std::error::Error>> {
+		self.get(uuid)?.running_provider(provider)
+	}
+
+	/// (uuid, provider) of every item a provider runs
+	pub fn running(&self) -> Vec<(String, String)> {
+		self.inner
+			.values()
+			.flat_map(|item| {
+				item.providers
+					.iter()
+					.filter(|(_, prov)| prov.running())
+					.map(move |(name, _)| (item.uuid.clone(), name.clone()))
+			})
+			.collect()
+	}
+
+	pub fn pids_provider(&self, uuid: &String, provider: String) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+		Ok(self.get(uuid)?.pids_provider(provider)?)
+	}
+
 	pub fn change_default_provider(
 		&mut self,
 		uuid: &String,
 		provider: String
 	) -> Result<(), Box<dyn std::error::Error>> {
 		self.get_mut(uuid)?.change_default_provider(provider)?;
+		self.persist(uuid);
 		Ok(())
 	}
 
+	/// removes module as provider of the item, and the item itself if
+	/// no providers are left
+	pub fn remove(&mut self, module: &str, uuid: &String) -> Result<ItemChange, Box<dyn std::error::Error>> {
+		let item = self.get_mut(uuid)?;
+		if !item.providers.contains_key(module) {
+			return Err(Box::new(ProvModuleNotAvailableError::new(module.to_string())));
+		}
+		let change = match item.remove_provider(module) {
+			true => ItemChange::Updated(uuid.clone()),
+			false => {
+				self.inner.remove(uuid);
+				ItemChange::Removed(uuid.clone())
+			}
+		};
+		self.persist(uuid);
+		Ok(change)
+	}
+
+	pub fn update(&mut self, module: &str, patch: library::LibraryItemPatch) -> Result<ItemChange, Box<dyn std::error::Error>> {
+		let uuid = patch.uuid.clone();
+		self.get_mut(&uuid)?.apply_patch(module, patch)?;
+		self.persist(&uuid);
+		Ok(ItemChange::Updated(uuid))
+	}
+
+	/// makes items the complete list of items module provides. Items
+	/// not in it lose the module as provider.
+	pub fn replace_all(
+		&mut self,
+		module: &'static str,
+		items: Vec<library::LibraryItemModule>,
+		mod_mgr: &ModuleManager
+	) -> Result<Vec<ItemChange>, ModuleNotAvailableError> {
+		let gone: Vec<String> = self
+			.inner
+			.iter()
+			.filter(|(uuid, item)| item.providers.contains_key(module) && !items.iter().any(|new| &new.uuid == *uuid))
+			.map(|(uuid, _)| uuid.clone())
+			.collect();
+		let mut changes: Vec<ItemChange> = items.iter().map(|item| ItemChange::Updated(item.uuid.clone())).collect();
+		self.bulk_insert(module, items, mod_mgr)?;
+		changes.extend(gone.iter().filter_map(|uuid| self.remove(module, uuid).ok()));
+		Ok(changes)
+	}
+
+	/// marks module as stale provider of every item, for modules that
+	/// were unloaded. Returns the uuids of the updated items.
+	pub fn mark_stale(&mut self, module: &str) -> Vec<String> {
+		self.inner
+			.iter_mut()
+			.filter_map(|(uuid, item)| item.mark_stale(module).then(|| uuid.clone()))
+			.collect()
+	}
+
 	pub fn to_frontend(&self) -> Vec<library::LibraryItemFrontend> {
 		self.inner.values().map(|item| item.to_frontend()).collect()
 	}
@@ -322,28 +916,100 @@ impl Library {
 #[derive(Debug)]
 #[allow(dead_code)]
 pub enum InternalCoreFutures {
-	NewFrontendRegistered(String, tokio::net::UnixStream),
-	ProcessDied(u32, i32 /* old pid, POSIX return code */),
+	NewFrontendRegistered(crate::frontend::FrontendInfo, tokio::net::UnixStream),
+	ProcessDied(u32, crate::process::Exit),
 	DatabaseProcessDied(std::io::Result<std::process::ExitStatus>),
+	/// a module finished unloading (id, library to load afterwards
+	/// and the delay before doing so)
+	ModuleUnloaded(
+		&'static str,
+		Option<(std::path::PathBuf, std::time::Duration)>,
+		Result<(), ModuleUnloadError>
+	),
+	/// the delay before restarting a module passed
+	RestartModule(&'static str, std::path::PathBuf),
+	/// a module to start is ready to be loaded (id it had before,
+	/// library and the opened module)
+	ModuleOpened(
+		&'static str,
+		std::path::PathBuf,
+		Result<crate::modules::modules::OpenedModule, Box<dyn std::error::Error + Send + Sync>>
+	),
+	/// progress held back by throttling is due (module, operation id)
+	FlushProgress(&'static str, String),
+	/// the time a closing item had to exit is over (uuid, provider,
+	/// pids)
+	CloseTimeout(String, &'static str, Vec<u32>),
+	/// the processes a launched one spawned were found (pid, tree)
+	ProcessAdopted(u32, Tree),
 	Debug,
 	Error(Box<dyn std::error::Error + Send>)
 }
 
+/// a process reported by a module and everything it spawned
+pub struct Running {
+	pub module: &'static str,
+	/// uuid of the library item
+	pub uuid: String,
+	/// only the process itself until it was adopted
+	pub tree: Tree,
+	/// asked to close, it's killed if it doesn't in time
+	pub closing: bool
+}
+
 pub struct Core {
 	pub library: Library,
 	pub internal_futures: FuturesUnordered<JoinHandle<InternalCoreFutures>>,
-	pub running: std::collections::HashMap<u32, (&'static str, String)>, /* pid, (module, uuid) */
+	/// by the pid the module reported
+	pub running: HashMap<u32, Running>,
 	// <(module uuid, pot. item uuid), (frontend uuid, msg resp uuid)>
 	pub open_preferences:
-		std::collections::HashMap<(String, utopia_common::library::preferences::DiagType), (String, Option<String>)>
+		std::collections::HashMap<(String, utopia_common::library::preferences::DiagType), (String, Option<String>)>,
+	pub operations: operations::Operations,
+	pub processes: UtopiaProcessesConfig
 }
 impl Core {
-	pub fn new() -> Self {
+	pub fn new(
+		store: store::LibraryStore,
+		progress_interval: std::time::Duration,
+		processes: UtopiaProcessesConfig
+	) -> Self {
 		Core {
-			library: Library::new(),
+			library: Library::new(store),
 			internal_futures: FuturesUnordered::new(),
 			running: std::collections::HashMap::new(),
-			open_preferences: std::collections::HashMap::new()
+			open_preferences: std::collections::HashMap::new(),
+			operations: operations::Operations::new(progress_interval),
+			processes
+		}
+	}
+
+	/// (pid, module id) of the processes the item runs as
+	pub fn pids(&self, uuid: &str) -> Vec<(u32, String)> {
+		let mut pids: Vec<(u32, String)> = self
+			.running
+			.iter()
+			.filter(|(_, running)| running.uuid == uuid)
+			.map(|(pid, running)| (*pid, running.module.to_string()))
+			.collect();
+		pids.sort_unstable();
+		pids
+	}
+
+	/// sends signal to the process trees of pids. Pids that aren't
+	/// tracked are skipped, they may belong to another process by now.
+	pub fn signal(&self, pids: &[u32], signal: libc::c_int) {
+		for pid in pids {
+			let running = match self.running.get(pid) {
+				Some(running) => running,
+				None => {
+					eprintln!("Failed sending signal {}: {}", signal, ProcessNotTrackedError::new(*pid));
+					continue;
+				}
+			};
+			if let Err(e) = running.tree.signal(signal) {
+				eprintln!("Failed sending signal {} to process {}: {}", signal, pid, e);
+			}
 		}
 	}
 }
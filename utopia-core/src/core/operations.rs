@@ -0,0 +1,110 @@
+//! Long-running operations of modules, see
+//! [Progress](utopia_common::module::ModuleCommands::Progress)
+//!
+//! Modules may report progress far more often than a frontend can
+//! draw it. Per operation, at most one update per interval is passed
+//! on; the latest one held back is sent once the interval is over.
+use std::{collections::HashMap,
+          time::{Duration, Instant}};
+
+use utopia_common::module::Progress;
+
+use crate::errors::OperationNotAvailableError;
+
+/// what to do with a progress update
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Forward {
+	Now,
+	/// flush the operation after the delay
+	Later(Duration),
+	/// a flush is already scheduled
+	Skip
+}
+
+struct Operation {
+	progress: Progress,
+	sent: Instant,
+	flush_scheduled: bool
+}
+
+pub struct Operations {
+	/// by (module, operation id)
+	inner: HashMap<(&'static str, String), Operation>,
+	interval: Duration
+}
+impl Operations {
+	pub fn new(interval: Duration) -> Self {
+		Operations {
+			inner: HashMap::new(),
+			interval
+		}
+	}
+
+	/// records progress of module. Finished operations are forgotten
+	/// and always forwarded.
+	pub fn update(&mut self, module: &'static str, progress: Progress) -> Forward {
+		let key = (module, progress.operation.clone());
+		if progress.finished {
+			self.inner.remove(&key);
+			return Forward::Now;
+		}
+		match self.inner.get_mut(&key) {
+			Some(op) => {
+				op.progress = progress;
+				let elapsed = op.sent.elapsed();
+				if elapsed >= self.interval {
+					op.sent = Instant::now();
+					Forward::Now
+				} else if op.flush_scheduled {
+					Forward::Skip
+				} else {
+					op.flush_scheduled = true;
+					Forward::Later(self.interval - elapsed)
+				}
+			},
+			None => {
+				self.inner.insert(key, Operation {
+					progress,
+					sent: Instant::now(),
+					flush_scheduled: false
+				});
+				Forward::Now
+			}
+		}
+	}
+
+	/// the latest progress of a scheduled flush, None if the operation
+	/// finished in the meantime
+	pub fn flush(&mut self, module: &'static str, operation: String) -> Option<Progress> {
+		let op = self.inner.get_mut(&(module, operation))?;
+		if !op.flush_scheduled {
+			return None;
+		}
+		op.flush_scheduled = false;
+		op.sent = Instant::now();
+		Some(op.progress.clone())
+	}
+
+	pub fn check_cancellable(&self, module: &str, operation: &str) -> Result<(), OperationNotAvailableError> {
+		match self
+			.inner
+			.iter()
+			.find(|((owner, id), _)| *owner == module && id == operation)
+		{
+			Some((_, op)) if op.progress.cancellable => Ok(()),
+			_ => Err(OperationNotAvailableError::new(module, operation))
+		}
+	}
+
+	/// forgets the operations of module, returning them as finished
+	pub fn remove_module(&mut self, module: &str) -> Vec<Progress> {
+		let keys: Vec<_> = self.inner.keys().filter(|(owner, _)| *owner == module).cloned().collect();
+		keys.into_iter()
+			.filter_map(|key| self.inner.remove(&key))
+			.map(|op| Progress {
+				finished: true,
+				..op.progress
+			})
+			.collect()
+	}
+}
@@ -0,0 +1,235 @@
+//! Filtering, sorting and paging of the library for
+//! [QueryLibrary](utopia_common::frontend::FrontendActions::QueryLibrary)
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+use utopia_common::library::{self,
+                             query::{LibraryPage, LibraryQuery, NameFilter, SortBy}};
+
+use super::{Library, LibraryItem};
+use crate::errors::{InvalidCursorError, InvalidQueryLimitError};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+enum SortKey {
+	Text(String),
+	Number(Option<u64>)
+}
+
+/// position of an item in the sorted result, which is what a cursor
+/// contains. The uuid breaks ties.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct Position(SortKey, String);
+
+/// how well name matches the filter, lower is better. None if it
+/// doesn't match at all.
+fn name_score(filter: &NameFilter, name: &str) -> Option<u64> {
+	let name = name.to_lowercase();
+	match filter {
+		NameFilter::Contains(part) => name.find(&part.to_lowercase()).map(|pos| pos as u64),
+		NameFilter::Fuzzy(pattern) => {
+			// every gap between matched characters counts, as does the
+			// position of the first one
+			let mut score = 0;
+			let mut chars = name.chars();
+			for wanted in pattern.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+				let skipped = chars.by_ref().position(|c| c == wanted)?;
+				score += skipped as u64;
+			}
+			Some(score)
+		}
+	}
+}
+
+fn status_matches(wanted: &library::LibraryItemStatus, status: &library::LibraryItemStatus) -> bool {
+	match (wanted, status) {
+		(library::LibraryItemStatus::Running(None), library::LibraryItemStatus::Running(_)) => true,
+		_ => wanted == status
+	}
+}
+
+impl LibraryItem {
+	fn matches(&self, query: &LibraryQuery) -> bool {
+		let status = || self.providers.values().flat_map(|provider| provider.status.iter());
+		let age = || self.details.age_rating.min_age();
+		(query.kinds.is_empty() || query.kinds.contains(&self.kind))
+			&& (query.genres.is_empty() || self.details.genre.iter().any(|g| query.genres.contains(g)))
+			&& (query.providers.is_empty() || query.providers.iter().any(|p| self.providers.contains_key(p)))
+			&& (query.status.is_empty() || status().any(|s| query.status.iter().any(|wanted| status_matches(wanted, s))))
+			&& query.max_age.is_none_or(|max| age().is_some_and(|age| age <= max))
+			&& (query.tags.is_empty() || self.details.tags.iter().any(|t| query.tags.contains(t)))
+	}
+
+	fn sort_key(&self, sort: SortBy, score: Option<u64>) -> SortKey {
+		match (sort, score) {
+			(SortBy::LastPlayed, _) => SortKey::Number(self.last_played),
+			(SortBy::Playtime, _) => SortKey::Number(Some(self.playtime)),
+			(SortBy::Relevance, Some(score)) => SortKey::Number(Some(score)),
+			(SortBy::Name, _) | (SortBy::Relevance, None) => SortKey::Text(self.name.to_lowercase())
+		}
+	}
+}
+
+impl Library {
+	/// runs query, returning one page of the result
+	pub fn query(&self, query: &LibraryQuery) -> Result<LibraryPage, Box<dyn std::error::Error>> {
+		run_query(self.inner.values(), query)
+	}
+}
+
+/// the page of items query asks for
+fn run_query<'a>(
+	items: impl Iterator<Item = &'a LibraryItem>,
+	query: &LibraryQuery
+) -> Result<LibraryPage, Box<dyn std::error::Error>> {
+	// an empty page would never lead to the next one
+	if query.limit == Some(0) {
+		return Err(Box::new(InvalidQueryLimitError::new(0)));
+	}
+	let after: Option<Position> = query
+		.cursor
+		.as_ref()
+		.map(|cursor| serde_json::from_str(cursor).map_err(|_| InvalidCursorError::new(cursor)))
+		.transpose()?;
+
+	let mut matching: Vec<(Position, &LibraryItem)> = items
+		.filter(|item| item.matches(query))
+		.filter_map(|item| {
+			let score = match &query.name {
+				Some(filter) => Some(name_score(filter, &item.name)?),
+				None => None
+			};
+			Some((Position(item.sort_key(query.sort, score), item.uuid.clone()), item))
+		})
+		.collect();
+	let order = |a: &Position, b: &Position| match query.descending {
+		true => b.cmp(a),
+		false => a.cmp(b)
+	};
+	matching.sort_by(|(a, _), (b, _)| order(a, b));
+
+	let total = matching.len();
+	let start = match &after {
+		Some(after) => matching.partition_point(|(pos, _)| order(pos, after) != Ordering::Greater),
+		None => 0
+	};
+	let end = match query.limit {
+		Some(limit) => total.min(start.saturating_add(limit)),
+		None => total
+	};
+	let page = &matching[start..end];
+	let next_cursor = match end < total {
+		true => page
+			.last()
+			.map(|(pos, _)| serde_json::to_string(pos).expect("cursor positions are serializable")),
+		false => None
+	};
+	Ok(LibraryPage {
+		items: page.iter().map(|(_, item)| item.to_frontend()).collect(),
+		total,
+		next_cursor
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	use utopia_common::library::{age_rating::AgeRating, credits::Credits, LibraryItemDetails, LibraryItemModule};
+
+	use super::*;
+
+	fn item(uuid: &str, name: &str, playtime: u64) -> LibraryItem {
+		let details = LibraryItemDetails {
+			age_rating: AgeRating {
+				pegi_rating: None,
+				esrb_rating: None,
+				fsk_rating: None
+			},
+			artworks: Vec::new(),
+			description: String::new(),
+			genre: Vec::new(),
+			game_modes: Vec::new(),
+			credits: Credits {
+				developer: String::new(),
+				publisher: None,
+				director: None,
+				other: HashMap::new()
+			},
+			controller_support: Vec::new(),
+			tags: Vec::new()
+		};
+		let mut item = LibraryItem::new("test", String::from("Test"), None, LibraryItemModule {
+			uuid: uuid.to_owned(),
+			name: name.to_owned(),
+			kind: library::LibraryItemKind::Game,
+			details,
+			status: Vec::new()
+		});
+		item.playtime = playtime;
+		item
+	}
+
+	fn uuids(page: &LibraryPage) -> Vec<&str> {
+		page.items.iter().map(|item| item.uuid.as_str()).collect()
+	}
+
+	#[test]
+	fn name_score_prefers_earlier_and_closer_matches() {
+		let contains = NameFilter::Contains(String::from("Craft"));
+		assert_eq!(name_score(&contains, "Minecraft"), Some(4));
+		assert_eq!(name_score(&contains, "Portal"), None);
+
+		let fuzzy = NameFilter::Fuzzy(String::from("h l"));
+		assert_eq!(name_score(&fuzzy, "Half-Life"), Some(1));
+		assert_eq!(name_score(&fuzzy, "The Long Dark"), Some(3));
+		assert_eq!(name_score(&fuzzy, "Portal"), None);
+	}
+
+	#[test]
+	fn sorts_with_the_uuid_breaking_ties() {
+		let items = [item("c", "Same", 5), item("a", "Same", 5), item("b", "other", 10)];
+		let mut query = LibraryQuery::default();
+		let page = run_query(items.iter(), &query).unwrap();
+		assert_eq!(uuids(&page), ["b", "a", "c"]);
+
+		query.sort = SortBy::Playtime;
+		query.descending = true;
+		let page = run_query(items.iter(), &query).unwrap();
+		assert_eq!(uuids(&page), ["b", "c", "a"]);
+	}
+
+	#[test]
+	fn cursor_continues_after_the_last_item() {
+		let items: Vec<LibraryItem> = ["a", "b", "c", "d", "e"].iter().map(|uuid| item(uuid, "Same", 0)).collect();
+		let mut query = LibraryQuery {
+			limit: Some(2),
+			..Default::default()
+		};
+		let mut seen = Vec::new();
+		loop {
+			let page = run_query(items.iter(), &query).unwrap();
+			assert_eq!(page.total, 5);
+			seen.extend(uuids(&page).into_iter().map(String::from));
+			match page.next_cursor {
+				Some(cursor) => query.cursor = Some(cursor),
+				None => break
+			}
+		}
+		assert_eq!(seen, ["a", "b", "c", "d", "e"]);
+	}
+
+	#[test]
+	fn rejects_invalid_limits_and_cursors() {
+		let items = [item("a", "A", 0)];
+		let query = LibraryQuery {
+			limit: Some(0),
+			..Default::default()
+		};
+		assert!(run_query(items.iter(), &query).unwrap_err().is::<InvalidQueryLimitError>());
+		let query = LibraryQuery {
+			cursor: Some(String::from("garbage")),
+			..Default::default()
+		};
+		assert!(run_query(items.iter(), &query).unwrap_err().is::<InvalidCursorError>());
+	}
+}
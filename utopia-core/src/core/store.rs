@@ -0,0 +1,97 @@
+//! Persistence of the library in the µtopia database
+//!
+//! Every item is stored as JSON in the hash [LIBRARY_KEY], keyed by
+//! its uuid. Only what survives a restart is stored, e.g. no running
+//! processes. The launch options of every item are stored as JSON in
+//! [LAUNCH_OPTIONS_KEY].
+use std::collections::HashMap;
+
+use redis::Commands;
+use serde::{Deserialize, Serialize};
+use utopia_common::library;
+
+/// redis hash containing the library
+pub const LIBRARY_KEY: &str = "utopia:library";
+/// redis string containing the launch options of every item
+pub const LAUNCH_OPTIONS_KEY: &str = "utopia:launch_options";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredProvider {
+	pub title: String,
+	pub icon: Option<String>,
+	pub status: Vec<library::LibraryItemStatus>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredItem {
+	pub uuid: String,
+	pub name: String,
+	pub kind: library::LibraryItemKind,
+	pub details: library::LibraryItemDetails,
+	/// module id of the provider chosen by the user
+	pub active_provider: String,
+	/// keyed by module id
+	pub providers: HashMap<String, StoredProvider>,
+	/// unix timestamp of the last launch
+	#[serde(default)]
+	pub last_played: Option<u64>,
+	/// seconds the item ran in total
+	#[serde(default)]
+	pub playtime: u64,
+	#[serde(default)]
+	pub last_crash: Option<library::Crash>,
+	#[serde(default)]
+	pub launch: library::LaunchOptions
+}
+
+pub struct LibraryStore {
+	connection: redis::Connection
+}
+impl LibraryStore {
+	pub fn new(connection: redis::Connection) -> Self {
+		LibraryStore {
+			connection
+		}
+	}
+
+	/// reads every stored item. Items that can't be read are skipped.
+	pub fn load(&mut self) -> redis::RedisResult<Vec<StoredItem>> {
+		let stored: HashMap<String, String> = self.connection.hgetall(LIBRARY_KEY)?;
+		Ok(stored
+			.into_iter()
+			.filter_map(|(uuid, json)| match serde_json::from_str(&json) {
+				Ok(item) => Some(item),
+				Err(e) => {
+					eprintln!("Skipping stored library item {}: {}", uuid, e);
+					None
+				}
+			})
+			.collect())
+	}
+
+	pub fn save(&mut self, item: &StoredItem) -> Result<(), Box<dyn std::error::Error>> {
+		let json = serde_json::to_string(item)?;
+		self.connection.hset::<_, _, _, ()>(LIBRARY_KEY, &item.uuid, json)?;
+		Ok(())
+	}
+
+	pub fn remove(&mut self, uuid: &str) -> redis::RedisResult<()> {
+		self.connection.hdel(LIBRARY_KEY, uuid)
+	}
+
+	/// the launch options of every item, the defaults if none are
+	/// stored yet
+	pub fn load_launch_options(&mut self) -> Result<library::LaunchOptions, Box<dyn std::error::Error>> {
+		let stored: Option<String> = self.connection.get(LAUNCH_OPTIONS_KEY)?;
+		match stored {
+			Some(json) => Ok(serde_json::from_str(&json)?),
+			None => Ok(library::LaunchOptions::default())
+		}
+	}
+
+	pub fn save_launch_options(&mut self, options: &library::LaunchOptions) -> Result<(), Box<dyn std::error::Error>> {
+		let json = serde_json::to_string(options)?;
+		self.connection.set::<_, _, ()>(LAUNCH_OPTIONS_KEY, json)?;
+		Ok(())
+	}
+}
@@ -21,7 +21,7 @@ impl UtopiaDatagramSocket {
 impl Drop for UtopiaDatagramSocket {
 	fn drop(&mut self) {
 		// There's no way to return a useful error here
-		let _ = std::fs::remove_file(&self.path).unwrap();
+		let _ = std::fs::remove_file(&self.path);
 	}
 }
 
@@ -63,7 +63,7 @@ pub async fn spawn(config: &crate::UtopiaDatabaseConfig) -> Result<(tokio::proce
 	write!(&mut file, "{}", &conf_str)?;
 
 	let listener = UtopiaDatagramSocket::bind(&config.ready_sock)?;
-	let db_server = tokio::process::Command::new("/usr/sbin/redis-server")
+	let db_server = tokio::process::Command::new(&config.server)
 		.arg(&path)
 		.env("NOTIFY_SOCKET", &config.ready_sock)
 		.spawn()?;
@@ -1,6 +1,11 @@
 use std::{error::Error,
           fmt::{self, Debug, Display, Formatter},
           path::PathBuf};
+
+use utopia_module::InterfaceVersion;
+
+use crate::modules::modules::ModuleKind;
+
 #[derive(Debug)]
 pub struct FileError {
 	path: PathBuf,
@@ -26,32 +31,188 @@ impl Display for FileError {
 	}
 }
 
+#[derive(Debug)]
+pub struct ConfigError {
+	origin: String,
+	key: Option<String>,
+	msg: String
+}
+impl ConfigError {
+	pub fn new(origin: impl Into<String>, key: Option<&str>, msg: impl Into<String>) -> Self {
+		ConfigError {
+			origin: origin.into(),
+			key: key.map(String::from),
+			msg: msg.into()
+		}
+	}
+}
+impl Error for ConfigError {}
+impl Display for ConfigError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		match &self.key {
+			Some(key) => write!(f, "Invalid configuration key {} in {}: {}", key, self.origin, self.msg),
+			None => write!(f, "Invalid configuration in {}: {}", self.origin, self.msg)
+		}
+	}
+}
+
 #[derive(Debug)]
 pub struct ModuleABIError {
-	name: &'static str,
-	version: &'static str,
-	expected: &'static str
+	path: PathBuf,
+	/// None for modules built before the interface was versioned
+	version: Option<InterfaceVersion>
 }
 impl ModuleABIError {
-	pub fn new(name: &'static str, version: &'static str, expected: &'static str) -> Self {
+	pub fn new(path: PathBuf, version: Option<InterfaceVersion>) -> Self {
 		ModuleABIError {
-			name,
-			version,
-			expected
+			path,
+			version
 		}
 	}
+
+	fn accepted(&self) -> String {
+		ModuleKind::of(&self.path).unwrap_or(ModuleKind::Library).accepted_versions()
+	}
 }
 impl Error for ModuleABIError {}
 impl Display for ModuleABIError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		match self.version {
+			Some(version) => write!(
+				f,
+				"The module {} implements interface version {}. µCore accepts {}",
+				self.path.to_string_lossy(),
+				version,
+				self.accepted()
+			),
+			None => write!(
+				f,
+				"The module {} predates the versioned module interface. µCore accepts {}",
+				self.path.to_string_lossy(),
+				self.accepted()
+			)
+		}
+	}
+}
+
+#[derive(Debug)]
+pub struct ManifestError {
+	path: PathBuf,
+	msg: String
+}
+impl ManifestError {
+	pub fn new(path: PathBuf, msg: impl Into<String>) -> Self {
+		ManifestError {
+			path,
+			msg: msg.into()
+		}
+	}
+}
+impl Error for ManifestError {}
+impl Display for ManifestError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "Invalid module manifest {}: {}", self.path.to_string_lossy(), self.msg)
+	}
+}
+
+#[derive(Debug)]
+pub struct NotAModuleError {
+	path: PathBuf
+}
+impl NotAModuleError {
+	pub fn new(path: PathBuf) -> Self {
+		NotAModuleError {
+			path
+		}
+	}
+}
+impl Error for NotAModuleError {}
+impl Display for NotAModuleError {
 	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
 		write!(
 			f,
-			"The module {} implements ABI version {}. µCore is built against {}",
-			self.name, self.version, self.expected
+			"{} does not export _utopia_module_descriptor, it's not a µtopia module",
+			self.path.to_string_lossy()
 		)
 	}
 }
 
+#[derive(Debug)]
+pub struct ModuleProcessError {
+	path: PathBuf,
+	msg: String
+}
+impl ModuleProcessError {
+	pub fn new(path: PathBuf, msg: impl Into<String>) -> Self {
+		ModuleProcessError {
+			path,
+			msg: msg.into()
+		}
+	}
+}
+impl Error for ModuleProcessError {}
+impl Display for ModuleProcessError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "Module process {} failed: {}", self.path.to_string_lossy(), self.msg)
+	}
+}
+
+#[derive(Debug)]
+pub struct ModuleDisabledError {
+	name: String
+}
+impl ModuleDisabledError {
+	pub fn new(name: &str) -> Self {
+		ModuleDisabledError {
+			name: name.to_owned()
+		}
+	}
+}
+impl Error for ModuleDisabledError {}
+impl Display for ModuleDisabledError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "The module {} is disabled by configuration", self.name)
+	}
+}
+
+#[derive(Debug)]
+pub struct ModuleAlreadyLoadedError {
+	id: &'static str
+}
+impl ModuleAlreadyLoadedError {
+	pub fn new(id: &'static str) -> Self {
+		ModuleAlreadyLoadedError {
+			id
+		}
+	}
+}
+impl Error for ModuleAlreadyLoadedError {}
+impl Display for ModuleAlreadyLoadedError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "A module with the id {} is already loaded", self.id)
+	}
+}
+
+#[derive(Debug)]
+pub struct ModuleUnloadError {
+	id: &'static str,
+	msg: &'static str
+}
+impl ModuleUnloadError {
+	pub fn new(id: &'static str, msg: &'static str) -> Self {
+		ModuleUnloadError {
+			id,
+			msg
+		}
+	}
+}
+impl Error for ModuleUnloadError {}
+impl Display for ModuleUnloadError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "Failed unloading module {}: {}", self.id, self.msg)
+	}
+}
+
 #[derive(Debug)]
 pub struct ModuleNotAvailableError {
 	name: &'static str
@@ -106,6 +267,162 @@ impl Display for LibraryItemNotAvailableError {
 	}
 }
 
+#[derive(Debug)]
+pub struct InvalidCursorError {
+	cursor: String
+}
+impl InvalidCursorError {
+	pub fn new(cursor: &str) -> Self {
+		InvalidCursorError {
+			cursor: cursor.to_owned()
+		}
+	}
+}
+impl Error for InvalidCursorError {}
+impl Display for InvalidCursorError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "Invalid library query cursor {}", self.cursor)
+	}
+}
+
+#[derive(Debug)]
+pub struct InvalidQueryLimitError {
+	limit: usize
+}
+impl InvalidQueryLimitError {
+	pub fn new(limit: usize) -> Self {
+		InvalidQueryLimitError {
+			limit
+		}
+	}
+}
+impl Error for InvalidQueryLimitError {}
+impl Display for InvalidQueryLimitError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "Invalid library query limit {}, pages hold at least one item", self.limit)
+	}
+}
+
+#[derive(Debug)]
+pub struct InvalidInstallDirError {
+	dir: PathBuf
+}
+impl InvalidInstallDirError {
+	pub fn new(dir: PathBuf) -> Self {
+		InvalidInstallDirError {
+			dir
+		}
+	}
+}
+impl Error for InvalidInstallDirError {}
+impl Display for InvalidInstallDirError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "Can't install to {}, the path has to be absolute", self.dir.to_string_lossy())
+	}
+}
+
+#[derive(Debug)]
+pub struct OperationNotAvailableError {
+	module: String,
+	operation: String
+}
+impl OperationNotAvailableError {
+	pub fn new(module: &str, operation: &str) -> Self {
+		OperationNotAvailableError {
+			module: module.to_owned(),
+			operation: operation.to_owned()
+		}
+	}
+}
+impl Error for OperationNotAvailableError {}
+impl Display for OperationNotAvailableError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "Module {} has no cancellable operation {}", self.module, self.operation)
+	}
+}
+
+#[derive(Debug)]
+pub struct ItemNotRunningError {
+	uuid: String,
+	provider: String
+}
+impl ItemNotRunningError {
+	pub fn new(uuid: &str, provider: &str) -> Self {
+		ItemNotRunningError {
+			uuid: uuid.to_owned(),
+			provider: provider.to_owned()
+		}
+	}
+}
+impl Error for ItemNotRunningError {}
+impl Display for ItemNotRunningError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "{} isn't running via {}", self.uuid, self.provider)
+	}
+}
+
+#[derive(Debug)]
+pub struct ItemStatusError {
+	uuid: String,
+	provider: String,
+	action: &'static str,
+	reason: &'static str
+}
+impl ItemStatusError {
+	pub fn new(uuid: &str, provider: &str, action: &'static str, reason: &'static str) -> Self {
+		ItemStatusError {
+			uuid: uuid.to_owned(),
+			provider: provider.to_owned(),
+			action,
+			reason
+		}
+	}
+}
+impl Error for ItemStatusError {}
+impl Display for ItemStatusError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "Can't {} {} via {}, {}", self.action, self.uuid, self.provider, self.reason)
+	}
+}
+
+#[derive(Debug)]
+pub struct ProcessNotTrackedError {
+	pid: u32
+}
+impl ProcessNotTrackedError {
+	pub fn new(pid: u32) -> Self {
+		ProcessNotTrackedError {
+			pid
+		}
+	}
+}
+impl Error for ProcessNotTrackedError {}
+impl Display for ProcessNotTrackedError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "No item is running as process {}", self.pid)
+	}
+}
+
+#[derive(Debug)]
+pub struct InvalidPreferenceError {
+	field: String,
+	reason: String
+}
+impl InvalidPreferenceError {
+	pub fn new(field: &str, reason: &str) -> Self {
+		InvalidPreferenceError {
+			field: field.to_owned(),
+			reason: reason.to_owned()
+		}
+	}
+}
+impl Error for InvalidPreferenceError {}
+impl Display for InvalidPreferenceError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "Invalid value of preference {}: {}", self.field, self.reason)
+	}
+}
+
 #[derive(Debug)]
 pub struct FrontendNotAvailableError {
 	name: String
@@ -124,6 +441,91 @@ impl Display for FrontendNotAvailableError {
 	}
 }
 
+#[derive(Debug)]
+pub struct SlowFrontendError {
+	name: String,
+	disconnected: bool
+}
+impl SlowFrontendError {
+	pub fn new(name: &str, disconnected: bool) -> Self {
+		SlowFrontendError {
+			name: name.to_owned(),
+			disconnected
+		}
+	}
+}
+impl Error for SlowFrontendError {}
+impl Display for SlowFrontendError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		match self.disconnected {
+			true => write!(f, "Frontend {} is too slow reading messages, disconnecting it", self.name),
+			false => write!(f, "Frontend {} is too slow reading messages, dropped a message", self.name)
+		}
+	}
+}
+
+#[derive(Debug)]
+pub struct FrameTooLargeError {
+	size: usize,
+	max: usize
+}
+impl FrameTooLargeError {
+	pub fn new(size: usize, max: usize) -> Self {
+		FrameTooLargeError {
+			size,
+			max
+		}
+	}
+}
+impl Error for FrameTooLargeError {}
+impl Display for FrameTooLargeError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "Frame of {} bytes exceeds the maximum frame size of {} bytes", self.size, self.max)
+	}
+}
+
+#[derive(Debug)]
+pub struct HandshakeError {
+	msg: String
+}
+impl HandshakeError {
+	pub fn new(msg: impl Into<String>) -> Self {
+		HandshakeError {
+			msg: msg.into()
+		}
+	}
+}
+impl Error for HandshakeError {}
+impl Display for HandshakeError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "Rejected frontend: {}", self.msg)
+	}
+}
+
+#[derive(Debug)]
+pub struct ProtocolVersionError {
+	version: u32,
+	expected: u32
+}
+impl ProtocolVersionError {
+	pub fn new(version: u32, expected: u32) -> Self {
+		ProtocolVersionError {
+			version,
+			expected
+		}
+	}
+}
+impl Error for ProtocolVersionError {}
+impl Display for ProtocolVersionError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(
+			f,
+			"Message uses protocol version {}, but version {} was agreed on in the handshake",
+			self.version, self.expected
+		)
+	}
+}
+
 #[derive(Debug)]
 pub struct UnkownUtopiaError<T: Debug> {
 	msg: &'static str,
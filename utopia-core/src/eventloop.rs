@@ -1,18 +1,22 @@
+use std::{path::PathBuf, time::Duration};
+
 use futures::{channel::mpsc, stream::StreamExt, FutureExt};
 use tokio::signal::unix::{signal, SignalKind};
 use utopia_common::{frontend, library, module};
 
-use crate::{core::{self, InternalCoreFutures},
+use crate::{core::{self, operations::Forward, InternalCoreFutures},
             errors,
-            frontend::{ev, socket::UtopiaSocket, SockStreamMap},
-            modules::ModuleCore};
+            frontend::{ev, socket::UtopiaSocket, subscriptions::Scope, SockStreamMap},
+            modules::{modules::{IModule, OpenedModule}, supervisor::Decision, ModuleCore},
+            process};
 pub struct EventLoop {
 	core: core::Core,
 	mods: ModuleCore,
 	channel: mpsc::UnboundedReceiver<(&'static str, module::ModuleCommands)>,
 	socket: UtopiaSocket,
 	connections: SockStreamMap,
-	db_pid: u32,
+	db_pid: Option<u32>,
+	#[allow(dead_code)]
 	database: redis::Client
 }
 
@@ -31,7 +35,7 @@ macro_rules! result_printer_resp {
 		if let Err(e) = res {
 			let resp = frontend::CoreEvent::new(frontend::CoreActions::Error(msg.to_string(), e.to_string()), msg_uuid);
 			result_printer!(
-				$self.connections.write_stream(&fe_uuid, resp).await,
+				$self.connections.write_stream(&fe_uuid, resp),
 				"Failed writing to FE"
 			);
 			eprintln!("{}: {}", msg, e);
@@ -44,28 +48,272 @@ impl EventLoop {
 		config: crate::UtopiaConfiguration,
 		mods: ModuleCore,
 		channel: mpsc::UnboundedReceiver<(&'static str, module::ModuleCommands)>,
-		mut db_process: tokio::process::Child,
+		db_process: Option<tokio::process::Child>,
 		database: (redis::Client, utopia_module::UDb)
 	) -> Self {
-		let db_pid = db_process.id().expect("Failed getting pid of database service");
 		let (database, _shared) = database;
-		let core = core::Core::new();
-		core.internal_futures.push(tokio::spawn(async move {
-			let res = db_process.wait().await;
-			InternalCoreFutures::DatabaseProcessDied(res)
-		}));
+		let store = core::store::LibraryStore::new(database.get_connection().expect("Could not connect to database"));
+		let core = core::Core::new(
+			store,
+			Duration::from_millis(config.frontend.progress_interval),
+			config.processes.clone()
+		);
+		let db_pid = db_process.map(|mut db_process| {
+			let db_pid = db_process.id().expect("Failed getting pid of database service");
+			core.internal_futures.push(tokio::spawn(async move {
+				let res = db_process.wait().await;
+				InternalCoreFutures::DatabaseProcessDied(res)
+			}));
+			db_pid
+		});
 
 		EventLoop {
 			core,
 			mods,
 			channel,
 			socket: UtopiaSocket::bind(config.socket).expect("Could not open socket"),
-			connections: SockStreamMap::new(),
+			connections: SockStreamMap::new(&config.frontend),
 			db_pid,
 			database
 		}
 	}
 
+	/// stops a module and marks it as stale provider in the library. Its
+	/// library is closed, and optionally loaded again after the given
+	/// delay, in the background once the module thread returned.
+	///
+	/// frontends are notified with state after the module is stopped.
+	fn unload_module(
+		&mut self,
+		module: &str,
+		state: frontend::ModuleState,
+		reload: Option<Duration>
+	) -> Result<(), Box<dyn std::error::Error>> {
+		let unloading = self.mods.mod_mgr.unload(module)?;
+		let module = unloading.id;
+		let reload = reload.map(|delay| (unloading.path.clone(), delay));
+		self.core.open_preferences.retain(|(owner, _), _| owner != module);
+		for progress in self.core.operations.remove_module(module) {
+			ev::send_progress(module, progress, &mut self.connections);
+		}
+		// items are only removed by the module itself, so e.g. the chosen
+		// provider survives it being loaded again. Processes it launched
+		// are still tracked by their watchers.
+		let changes: Vec<core::ItemChange> =
+			self.core.library.mark_stale(module).into_iter().map(core::ItemChange::Updated).collect();
+		ev::send_item_changes(&self.core.library, &changes, module, &mut self.connections);
+		ev::send_module_state(module, state, &mut self.connections);
+		self.core.internal_futures.push(tokio::spawn(async move {
+			let res = unloading.finish().await;
+			InternalCoreFutures::ModuleUnloaded(module, reload, res)
+		}));
+		Ok(())
+	}
+
+	/// asks provider, the active one if None, to close the item, which
+	/// is Closing until its processes exited. They are sent SIGTERM,
+	/// unless the module closes its items itself, and killed once the
+	/// close timeout is over.
+	fn close_item(&mut self, uuid: &String, provider: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+		let (provider, pids) = self.core.library.running_provider(uuid, provider)?;
+		let closes_items = |module: &IModule| module.module.get_module_info().closes_items;
+		// without pids the module is the only one who can close it
+		match pids.is_empty() || self.mods.mod_mgr.get(provider).is_ok_and(closes_items) {
+			true => self
+				.mods
+				.mod_mgr
+				.get(provider)?
+				.send(module::CoreCommands::CloseLibraryItem(uuid.clone()))?,
+			false => self.core.signal(&pids, libc::SIGTERM)
+		}
+		self.closing(uuid, provider, pids)
+	}
+
+	/// like [close_item](Self::close_item), but only for one process.
+	/// Modules can't close single processes, so it's always sent
+	/// SIGTERM. The item is only Closing if it's its last process.
+	fn close_process(&mut self, pid: u32) -> Result<(), Box<dyn std::error::Error>> {
+		let running = self.core.running.get(&pid).ok_or_else(|| errors::ProcessNotTrackedError::new(pid))?;
+		let (provider, uuid) = (running.module, running.uuid.clone());
+		self.core.signal(&[pid], libc::SIGTERM);
+		match self.core.library.get(&uuid)?.runs_besides(provider, pid) {
+			true => {
+				self.close_pids(&uuid, provider, vec![pid]);
+				Ok(())
+			},
+			false => self.closing(&uuid, provider, vec![pid])
+		}
+	}
+
+	/// kills the process trees of pids. They count as closing, so they
+	/// aren't recorded as crashes.
+	fn kill(&mut self, pids: &[u32]) {
+		for pid in pids {
+			if let Some(running) = self.core.running.get_mut(pid) {
+				running.closing = true;
+			}
+		}
+		self.core.signal(pids, libc::SIGKILL);
+	}
+
+	/// closes every running item, returning the errors of the ones that
+	/// failed
+	fn close_all(&mut self) -> Vec<Box<dyn std::error::Error>> {
+		self.core
+			.library
+			.running()
+			.into_iter()
+			.filter_map(|(uuid, provider)| self.close_item(&uuid, Some(provider)).err())
+			.collect()
+	}
+
+	/// marks the item as Closing, see [close_pids](Self::close_pids)
+	fn closing(&mut self, uuid: &String, provider: &'static str, pids: Vec<u32>) -> Result<(), Box<dyn std::error::Error>> {
+		self.core.library.replace_state(provider, uuid, &[], Some(library::LibraryItemStatus::Closing))?;
+		ev::send_updated_item(&self.core.library, uuid, frontend::Topic::Processes, provider, &mut self.connections);
+		self.close_pids(uuid, provider, pids);
+		Ok(())
+	}
+
+	/// marks pids as closing and schedules killing them, or giving up on
+	/// the module closing the item
+	fn close_pids(&mut self, uuid: &str, provider: &'static str, pids: Vec<u32>) {
+		for pid in &pids {
+			if let Some(running) = self.core.running.get_mut(pid) {
+				running.closing = true;
+			}
+		}
+		let timeout = Duration::from_secs(self.core.processes.close_timeout);
+		let uuid = uuid.to_owned();
+		self.core.internal_futures.push(tokio::spawn(async move {
+			tokio::time::sleep(timeout).await;
+			InternalCoreFutures::CloseTimeout(uuid, provider, pids)
+		}));
+	}
+
+	/// tracks a process a module reported to run an item as, and adopts
+	/// the processes it spawns in the background
+	fn item_launched(&mut self, module: &'static str, uuid: String, pid: u32) {
+		// unknown items can't show that they run
+		if let Err(e) = self.core.library.started(module, &uuid, pid) {
+			eprintln!("Failed recording launch of {}: {}", uuid, e);
+			return;
+		}
+		ev::send_updated_item(&self.core.library, &uuid, frontend::Topic::Processes, module, &mut self.connections);
+		self.core.running.insert(pid, core::Running {
+			module,
+			uuid,
+			tree: process::tree::Tree::Single(pid),
+			closing: false
+		});
+		// searching /proc takes a while
+		self.core.internal_futures.push(tokio::spawn(async move {
+			let tree = tokio::task::spawn_blocking(move || process::tree::Tree::adopt(pid))
+				.await
+				.unwrap_or(process::tree::Tree::Single(pid));
+			InternalCoreFutures::ProcessAdopted(pid, tree)
+		}));
+	}
+
+	/// a module reported that the processes of an item exited, with
+	/// info if it crashed
+	fn item_closed(&mut self, module: &'static str, uuid: &String, crash: Option<library::CrashInfo>) {
+		// processes µCore tracks are cleared by their watchers, so they
+		// can still be killed if they don't exit
+		if let Err(e) = self.core.library.closed(module, uuid) {
+			eprintln!("Failed clearing running state of {}: {}", uuid, e);
+			return;
+		}
+		if let Some(info) = crash {
+			match self.core.library.crashed(module, uuid, info) {
+				Ok(crash) => ev::send_crash(uuid, crash, module, &mut self.connections),
+				Err(e) => eprintln!("Failed recording crash: {}", e)
+			}
+		}
+		ev::send_updated_item(&self.core.library, uuid, frontend::Topic::Processes, module, &mut self.connections);
+	}
+
+	/// tracks the process tree of a launched process and watches it
+	/// until every process of it exited
+	///
+	/// signals sent before only reached the process itself, a closing
+	/// item is still killed as a whole once the close timeout is over.
+	fn process_adopted(&mut self, pid: u32, tree: process::tree::Tree) {
+		if let Some(running) = self.core.running.get_mut(&pid) {
+			running.tree = tree.clone();
+		}
+		// the cgroup is released even if the module was unloaded
+		self.core.internal_futures.push(tokio::spawn(async move {
+			let exit = process::wait(pid).await;
+			tree.wait().await;
+			tree.release();
+			InternalCoreFutures::ProcessDied(pid, exit)
+		}));
+	}
+
+	/// state reported to frontends for a supervisor decision and the
+	/// delay before restarting the module, if it is restarted
+	fn decision_state(decision: Decision) -> (frontend::ModuleState, Option<Duration>) {
+		match decision {
+			Decision::Restart(delay) => {
+				println!("Restarting module in {}s", delay.as_secs());
+				(frontend::ModuleState::Restarting(delay.as_secs()), Some(delay))
+			},
+			Decision::WaitForDependency => (frontend::ModuleState::WaitingForDependency, None),
+			Decision::GiveUp(failures) => {
+				eprintln!("Module failed {} times in a row, giving up on it", failures);
+				(frontend::ModuleState::Failed, None)
+			},
+			Decision::Never => (frontend::ModuleState::Unloaded, None)
+		}
+	}
+
+	fn schedule_restart(&mut self, module: &'static str, path: PathBuf, delay: Duration) {
+		self.core.internal_futures.push(tokio::spawn(async move {
+			tokio::time::sleep(delay).await;
+			InternalCoreFutures::RestartModule(module, path)
+		}));
+	}
+
+	/// opens a module again in the background, it's loaded once it's
+	/// ready, see [module_opened](Self::module_opened)
+	fn start_module(&mut self, module: &'static str, path: PathBuf) {
+		let open = unsafe { self.mods.mod_mgr.open(path.clone()) };
+		self.core.internal_futures.push(tokio::spawn(async move {
+			InternalCoreFutures::ModuleOpened(module, path, open.await)
+		}));
+	}
+
+	/// loads a module that was started again and starts the modules
+	/// that were waiting for it to come back. Failing to load counts as
+	/// another failure of the module.
+	fn module_opened(
+		&mut self,
+		module: &'static str,
+		path: PathBuf,
+		opened: Result<OpenedModule, Box<dyn std::error::Error + Send + Sync>>
+	) {
+		let loaded = opened
+			.map_err(|e| e as Box<dyn std::error::Error>)
+			.and_then(|opened| self.mods.load(opened));
+		match loaded {
+			Ok(id) => {
+				ev::send_module_state(id, frontend::ModuleState::Loaded, &mut self.connections);
+				for (module, path) in self.mods.supervisor.dependency_returned(id) {
+					self.start_module(module, path);
+				}
+			},
+			Err(e) => {
+				eprintln!("Failed loading module {} from {}: {}", module, path.to_string_lossy(), e);
+				let (state, restart) = Self::decision_state(self.mods.supervisor.died(module, None));
+				ev::send_module_state(module, state, &mut self.connections);
+				if let Some(delay) = restart {
+					self.schedule_restart(module, path, delay);
+				}
+			}
+		}
+	}
+
 	pub async fn run(&mut self) {
 		let mut exit_signal = signal(SignalKind::quit()).expect("Failure creating SIGQUIT stream. Are you on Unix?");
 		loop {
@@ -75,19 +323,69 @@ impl EventLoop {
 					match com {
 						Ok(msg) => {
 							match msg {
-								InternalCoreFutures::NewFrontendRegistered(name, stream) => {
-									if let Err(e) = self.connections.insert(name, stream).await {
+								InternalCoreFutures::NewFrontendRegistered(info, stream) => {
+									if let Err(e) = self.connections.insert(info, stream) {
 										eprintln!("Failed to add stream to StreamMap: {}", e);
 									}
 								},
-								InternalCoreFutures::ProcessDied(pid, status) => {
-									if status != 0 {
-										eprintln!("Process {} died with an non-zero exit code: {}", pid, status);
+								InternalCoreFutures::ProcessDied(pid, exit) => {
+									let crash = exit.crash_info();
+									if crash.is_some() {
+										eprintln!("Process {} died with {}", pid, exit);
+									}
+									if let Some(core::Running { module, uuid, closing, .. }) = self.core.running.remove(&pid) {
+										let closing_left = self
+											.core
+											.running
+											.values()
+											.any(|other| other.closing && other.module == module && other.uuid == uuid);
+										result_printer!(self.core.library.exited(module, &uuid, pid, closing_left), "Failed remove running state to provider");
+										// being terminated or killed on close is no crash
+										if let Some(info) = crash.filter(|_| !closing) {
+											match self.core.library.crashed(module, &uuid, info) {
+												Ok(crash) => ev::send_crash(&uuid, crash, module, &mut self.connections),
+												Err(e) => eprintln!("Failed recording crash: {}", e)
+											}
+										}
+										ev::send_updated_item(&self.core.library, &uuid, frontend::Topic::Processes, module, &mut self.connections);
+									}
+								},
+								InternalCoreFutures::ModuleUnloaded(module, reload, res) => {
+									match (res, reload) {
+										(Ok(()), Some((path, delay))) if delay.as_secs() == 0 => self.start_module(module, path),
+										(Ok(()), Some((path, delay))) => self.schedule_restart(module, path, delay),
+										(Ok(()), None) => println!("Module {} unloaded", module),
+										// dlopen would hand out the old library again
+										(Err(e), Some(_)) => eprintln!("{}, not reloading it", e),
+										(Err(e), None) => eprintln!("{}", e)
+									}
+								},
+								InternalCoreFutures::RestartModule(module, path) => self.start_module(module, path),
+								InternalCoreFutures::ModuleOpened(module, path, opened) => self.module_opened(module, path, opened),
+								InternalCoreFutures::FlushProgress(module, operation) => {
+									if let Some(progress) = self.core.operations.flush(module, operation) {
+										ev::send_progress(module, progress, &mut self.connections);
 									}
-									if let Some((module, uuid)) = self.core.running.remove(&pid) {
-										result_printer!(self.core.library.get_mut(&uuid).expect("FIX ME").update_state(module.to_string(), core::UpdStateAction::Remove, library::LibraryItemStatus::Running(Some(pid))),
-											"Failed remove running state to provider");
-										ev::send_updated_item(&self.core.library, &uuid, &mut self.connections).await;
+								},
+								InternalCoreFutures::ProcessAdopted(pid, tree) => self.process_adopted(pid, tree),
+								InternalCoreFutures::CloseTimeout(uuid, provider, pids) => {
+									// pids may have been reused by then
+									let left: Vec<u32> = pids
+										.into_iter()
+										.filter(|pid| self.core.running.get(pid).is_some_and(|running| running.closing))
+										.collect();
+									if !left.is_empty() {
+										eprintln!("{} didn't close within {}s, killing it", uuid, self.core.processes.close_timeout);
+										self.core.signal(&left, libc::SIGKILL);
+									}
+									// processes without a pid can't be killed, they keep running
+									match self.core.library.close_expired(provider, &uuid) {
+										Ok(true) => {
+											eprintln!("{} wasn't closed by {} within {}s", uuid, provider, self.core.processes.close_timeout);
+											ev::send_updated_item(&self.core.library, &uuid, frontend::Topic::Processes, provider, &mut self.connections);
+										},
+										Ok(false) => (),
+										Err(e) => eprintln!("Failed clearing closing state of {}: {}", uuid, e)
 									}
 								},
 								InternalCoreFutures::DatabaseProcessDied(res) => {
@@ -105,9 +403,10 @@ impl EventLoop {
 				con = self.socket.next() => {
 					match con.unwrap() {
 						Ok((stream, _addr)) => {
+							let max_frame_size = self.connections.max_frame_size();
 							self.core.internal_futures.push(tokio::spawn(async move {
-								match SockStreamMap::accept_handshake(stream).await {
-									Ok((name, stream)) => InternalCoreFutures::NewFrontendRegistered(name, stream),
+								match SockStreamMap::accept_handshake(stream, max_frame_size).await {
+									Ok((info, stream)) => InternalCoreFutures::NewFrontendRegistered(info, stream),
 									Err(e) => {
 										eprintln!("FE Handshake failed: {}", e);
 										InternalCoreFutures::Error(Box::new(errors::UnkownUtopiaError::new("FE Handshake failed", 0)))
@@ -126,23 +425,23 @@ impl EventLoop {
 								match msg.action {
 									frontend::FrontendActions::GetGameLibrary => {
 										let library = frontend::CoreEvent::new(frontend::CoreActions::ResponseGameLibrary(self.core.library.to_frontend()), msg.uuid.clone());
-										result_printer!(self.connections.write_stream(&uuid, library).await, "Failed writing to FE"); //TODO: Don't block
-										//result_printer_resp!(self, (self.connections.write_stream(uuid, library).await, "Failed writing to FE"), (msg.uuid, uuid.clone()));
+										result_printer!(self.connections.write_stream(&uuid, library), "Failed writing to FE");
+										//result_printer_resp!(self, (self.connections.write_stream(uuid, library), "Failed writing to FE"), (msg.uuid, uuid.clone()));
 									},
 									frontend::FrontendActions::GetFullGameLibrary => {
 										let library = frontend::CoreEvent::new(frontend::CoreActions::ResponseFullGameLibrary(self.core.library.to_full()), msg.uuid.clone());
-										result_printer!(self.connections.write_stream(&uuid, library).await, "Failed writing to FE"); //TODO: Don't block
+										result_printer!(self.connections.write_stream(&uuid, library), "Failed writing to FE");
 									},
 									frontend::FrontendActions::GetGameDetails(guuid) => {
 										println!("FE {} requested game details of {}", uuid, guuid);
 										match self.core.library.get(&guuid) {
 											Ok(item) => {
 												let details = frontend::CoreEvent::new(frontend::CoreActions::ResponseItemDetails(item.details.clone()), msg.uuid);
-												result_printer!(self.connections.write_stream(&uuid, details).await, "Failed writing to FE"); //TODO: Don't block
+												result_printer!(self.connections.write_stream(&uuid, details), "Failed writing to FE");
 											},
 											Err(e) => {
 												let resp = frontend::CoreEvent::new(frontend::CoreActions::Error(String::from("Failed to get library item"), e.to_string()), msg.uuid);
-												result_printer!(self.connections.write_stream(&uuid, resp).await, "Failed writing to FE");
+												result_printer!(self.connections.write_stream(&uuid, resp), "Failed writing to FE");
 												eprintln!("Failed to get library item: {}", e)
 											}
 										}
@@ -160,7 +459,8 @@ impl EventLoop {
 												match self.core.library.get(&guuid) {
 													Ok(item) => {
 														let details = frontend::CoreEvent::new(frontend::CoreActions::ResponseGameUpdate(item.to_frontend()), msg.uuid);
-														result_printer!(self.connections.broadcast_stream(details).await, "Failed writing to FE");
+														let scope = Scope::new(frontend::Topic::Library).item(&guuid);
+														result_printer!(self.connections.publish(scope, details), "Failed writing to FE");
 													},
 													Err(_e) => eprintln!("FE {} requested nonexistant item: {}", &uuid, guuid)
 												};
@@ -168,32 +468,92 @@ impl EventLoop {
 											frontend::library::LibraryItemProviderMethods::Close(quit) => {
 												match quit {
 													frontend::library::LibraryItemProviderQuitActions::ActiveProvider(item) => {
-														if let Ok(item) = self.core.library.get(&item) {
-															result_printer_resp!(self, (item.close_default(&self.mods.mod_mgr), "Error closing item"), (msg.uuid, &uuid));
-														}
+														result_printer_resp!(self, (self.close_item(&item, None), "Error closing item"), (msg.uuid, &uuid));
 													},
 													frontend::library::LibraryItemProviderQuitActions::OfProvider(item, provider) => {
-														if let Ok(item) = self.core.library.get(&item) {
-															result_printer_resp!(self, (item.close_provider(&self.mods.mod_mgr, provider), "Error closing item of provider"), (msg.uuid, &uuid));
-														}
+														result_printer_resp!(self, (self.close_item(&item, Some(provider)), "Error closing item of provider"), (msg.uuid, &uuid));
+													},
+													frontend::library::LibraryItemProviderQuitActions::Specific(pid) => {
+														result_printer_resp!(self, (self.close_process(pid), "Error closing process"), (msg.uuid, &uuid));
 													},
-													_ => eprintln!("FE {} requested unimplemented quit method {:?}", uuid, quit),
+													frontend::library::LibraryItemProviderQuitActions::All => {
+														for e in self.close_all() {
+															result_printer_resp!(self, (Err::<(), _>(e), "Error closing item"), (msg.uuid.clone(), &uuid));
+														}
+													}
 												}
 											},
 											frontend::library::LibraryItemProviderMethods::Kill(quit) => {
 												match quit {
 													frontend::library::LibraryItemProviderQuitActions::ActiveProvider(item) => {
 														if let Ok(item) = self.core.library.get(&item) {
-															item.kill_default();
+															let pids = item.pids_default();
+															self.kill(&pids);
 														}
 													},
 													frontend::library::LibraryItemProviderQuitActions::OfProvider(item, provider) => {
-														result_printer_resp!(self, (self.core.library.kill_provider(&item, provider), "Error killing item of provider"), (msg.uuid, &uuid));
+														match self.core.library.pids_provider(&item, provider) {
+															Ok(pids) => self.kill(&pids),
+															Err(e) => {
+																result_printer_resp!(self, (Err::<(), _>(e), "Error killing item of provider"), (msg.uuid, &uuid));
+															}
+														}
+													},
+													frontend::library::LibraryItemProviderQuitActions::Specific(pid) => {
+														match self.core.running.contains_key(&pid) {
+															true => self.kill(&[pid]),
+															false => {
+																result_printer_resp!(self, (Err::<(), _>(Box::new(errors::ProcessNotTrackedError::new(pid))), "Error killing process"), (msg.uuid, &uuid));
+															}
+														}
+													},
+													frontend::library::LibraryItemProviderQuitActions::All => {
+														let pids: Vec<u32> = self.core.running.keys().copied().collect();
+														self.kill(&pids);
+													}
+												}
+											},
+											frontend::library::LibraryItemProviderMethods::Update(guuid) => {
+												match self.core.library.update_library_item(&guuid, &self.mods.mod_mgr) {
+													Ok(module) => ev::send_updated_item(&self.core.library, &guuid, frontend::Topic::ItemStatus, module, &mut self.connections),
+													Err(e) => {
+														result_printer_resp!(self, (Err::<(), _>(e), "Error updating item"), (msg.uuid, &uuid));
+													}
+												}
+											},
+											frontend::library::LibraryItemProviderMethods::Uninstall(guuid) => {
+												result_printer_resp!(self, (self.core.library.uninstall_library_item(&guuid, &self.mods.mod_mgr), "Error uninstalling item"), (msg.uuid, &uuid));
+											},
+											frontend::library::LibraryItemProviderMethods::Install(guuid, provider, options) => {
+												match self.core.library.install_library_item(&guuid, &provider, options, &self.mods.mod_mgr) {
+													Ok(()) => ev::send_updated_item(&self.core.library, &guuid, frontend::Topic::ItemStatus, &provider, &mut self.connections),
+													Err(e) => {
+														result_printer_resp!(self, (Err::<(), _>(e), "Error installing item"), (msg.uuid, &uuid));
+													}
+												}
+											},
+											frontend::library::LibraryItemProviderMethods::GetPid(guuid) => {
+												match self.core.library.get(&guuid) {
+													Ok(_) => {
+														let resp = frontend::CoreEvent::new(frontend::CoreActions::ResponsePids(guuid.clone(), self.core.pids(&guuid)), msg.uuid);
+														result_printer!(self.connections.write_stream(&uuid, resp), "Failed writing to FE");
 													},
-													_ => eprintln!("FE {} requested unimplemented quit method {:?}", uuid, quit),
+													Err(e) => {
+														result_printer_resp!(self, (Err::<(), _>(Box::new(e)), "Error getting pids of item"), (msg.uuid, &uuid));
+													}
 												}
+											}
+										}
+									},
+									frontend::FrontendActions::RequestPreferenceDiag(module, item) if module == frontend::CORE_ID => {
+										match self.core.library.launch_diag(&item) {
+											Ok(diag) => {
+												let resp = frontend::CoreEvent::new(frontend::CoreActions::PreferenceDiagResponse((module, item), diag), msg.uuid);
+												result_printer!(self.connections.write_stream(&uuid, resp), "Failed writing to FE");
 											},
-											_ => eprintln!("FE {} requested unimplemented method {:?}", uuid, method),
+											Err(e) => {
+												result_printer_resp!(self, (Err::<(), _>(e), "Error getting launch options"), (msg.uuid, &uuid));
+											}
 										}
 									},
 									frontend::FrontendActions::RequestPreferenceDiag(module, item) => {
@@ -202,14 +562,60 @@ impl EventLoop {
 											result_printer!(imodule.send(utopia_common::module::CoreCommands::RequestPreferenceDiag(item)), "Failed messaging module")
 										};
 									},
+									frontend::FrontendActions::PreferenceDiagUpdate((module, itype), values) if module == frontend::CORE_ID => {
+										match self.core.library.update_launch_options(&itype, values) {
+											Ok(()) => ev::send_preferences_changed(&module, itype, &mut self.connections),
+											Err(e) => {
+												result_printer_resp!(self, (Err::<(), _>(e), "Error changing launch options"), (msg.uuid, &uuid));
+											}
+										}
+									},
 									frontend::FrontendActions::PreferenceDiagUpdate((module, itype), values) => {
 										if let Ok(imodule) = self.mods.mod_mgr.get_owned(&module) {
-											result_printer!(imodule.send(utopia_common::module::CoreCommands::PreferenceDiagUpdate(itype, values)), "Failed messaging module")
+											match imodule.send(utopia_common::module::CoreCommands::PreferenceDiagUpdate(itype.clone(), values)) {
+												Ok(()) => ev::send_preferences_changed(&module, itype, &mut self.connections),
+												Err(e) => eprintln!("Failed messaging module: {}", e)
+											}
+										}
+									},
+									frontend::FrontendActions::UnloadModule(module) => {
+										self.mods.supervisor.forget(&module);
+										result_printer_resp!(self, (self.unload_module(&module, frontend::ModuleState::Unloaded, None), "Error unloading module"), (msg.uuid, &uuid));
+									},
+									frontend::FrontendActions::ReloadModule(module) => {
+										self.mods.supervisor.forget(&module);
+										result_printer_resp!(self, (self.unload_module(&module, frontend::ModuleState::Unloaded, Some(Duration::from_secs(0))), "Error reloading module"), (msg.uuid, &uuid));
+									},
+									frontend::FrontendActions::Subscribe(subscription) => {
+										result_printer!(self.connections.subscribe(&uuid, subscription), "Failed subscribing FE");
+									},
+									frontend::FrontendActions::Unsubscribe(topics) => {
+										result_printer!(self.connections.unsubscribe(&uuid, &topics), "Failed unsubscribing FE");
+									},
+									frontend::FrontendActions::CancelOperation(id, operation) => {
+										let cancel = || -> Result<(), Box<dyn std::error::Error>> {
+											self.core.operations.check_cancellable(&id, &operation)?;
+											self.mods.mod_mgr.get_owned(&id)?.send(module::CoreCommands::CancelOperation(operation))?;
+											Ok(())
+										};
+										result_printer_resp!(self, (cancel(), "Error cancelling operation"), (msg.uuid, &uuid));
+									},
+									frontend::FrontendActions::QueryLibrary(query) => {
+										match self.core.library.query(&query) {
+											Ok(page) => {
+												let page = frontend::CoreEvent::new(frontend::CoreActions::ResponseLibraryQuery(page), msg.uuid);
+												result_printer!(self.connections.write_stream(&uuid, page), "Failed writing to FE");
+											},
+											Err(e) => {
+												result_printer_resp!(self, (Err::<(), _>(e), "Failed to query library"), (msg.uuid, &uuid));
+											}
 										}
 									}
 								}
 							},
-							Err(e) => eprintln!("Received invalid message from {}: {}", uuid, e)
+							Err(e) => {
+								result_printer_resp!(self, (Err::<(), _>(e), &format!("Received invalid message from {}", uuid)), (None, &uuid));
+							}
 						}
 					}
 				}
@@ -219,37 +625,87 @@ impl EventLoop {
 					match msg {
 						Some((uuid, cmd)) => {
 							match cmd {
-								module::ModuleCommands::Refresh => println!("Module wants to force a FE refresh"),
+								module::ModuleCommands::Refresh => ev::send_refresh(uuid, &mut self.connections),
 								module::ModuleCommands::AddLibraryItem(item) =>
 									result_printer!(self.core.library.insert(uuid, item, &self.mods.mod_mgr), "Error adding an item to library"),
 								module::ModuleCommands::AddLibraryItemBulk(items) =>
 									result_printer!(self.core.library.bulk_insert(uuid, items, &self.mods.mod_mgr), "Error adding items to library"),
 								module::ModuleCommands::ItemStatusSignal(sig) => {
 									match sig {
-										module::LibraryItemStatusSignals::Launched(guid, pid) => {
-											result_printer!(self.core.library.get_mut(&guid).expect("FIX ME").update_state(uuid.to_string(), core::UpdStateAction::Add, library::LibraryItemStatus::Running(Some(pid))),
-												"Failed add running state to provider");
-											/*let details = frontend::CoreEvent::new(frontend::CoreActions::SignalGameLaunch(guid), None);
-											result_printer!(self.connections.broadcast_stream(details).await, "Failed writing to FE"); //TODO: Don't block*/
-											self.core.running.insert(pid, (uuid, guid.clone()));
-											self.core.internal_futures.push(tokio::spawn(async move {
-												let status = unsafe {
-													let mut status: libc::c_int = 0;
-													libc::waitpid(pid as i32, &mut status, 0);
-													status
-												};
-												InternalCoreFutures::ProcessDied(pid, status)
-											}));
-											ev::send_updated_item(&self.core.library, &guid, &mut self.connections).await;
+										module::LibraryItemStatusSignals::Launched(guid, pid) => self.item_launched(uuid, guid, pid),
+										module::LibraryItemStatusSignals::Updated(guid) => {
+											match self.core.library.replace_state(uuid, &guid, &[library::LibraryItemStatus::Updating, library::LibraryItemStatus::Updatable], Some(library::LibraryItemStatus::Installed)) {
+												Ok(()) => ev::send_updated_item(&self.core.library, &guid, frontend::Topic::ItemStatus, uuid, &mut self.connections),
+												Err(e) => eprintln!("Failed marking item as updated: {}", e)
+											}
+										},
+										module::LibraryItemStatusSignals::UpdateFailed(guid, reason) => {
+											match self.core.library.replace_state(uuid, &guid, &[library::LibraryItemStatus::Updating], Some(library::LibraryItemStatus::Updatable)) {
+												Ok(()) => ev::send_updated_item(&self.core.library, &guid, frontend::Topic::ItemStatus, uuid, &mut self.connections),
+												Err(e) => eprintln!("Failed resetting status of item: {}", e)
+											}
+											ev::send_item_error(&guid, reason, uuid, &mut self.connections);
+										},
+										module::LibraryItemStatusSignals::Uninstalled(guid) => {
+											match self.core.library.replace_state(uuid, &guid, &[library::LibraryItemStatus::Installed, library::LibraryItemStatus::Updatable, library::LibraryItemStatus::Updating], Some(library::LibraryItemStatus::NotInstalled)) {
+												Ok(()) => ev::send_updated_item(&self.core.library, &guid, frontend::Topic::ItemStatus, uuid, &mut self.connections),
+												Err(e) => eprintln!("Failed marking item as uninstalled: {}", e)
+											}
 										},
-										_ => println!("Signal from module: {:?}", sig)
+										module::LibraryItemStatusSignals::Closed(guid) => self.item_closed(uuid, &guid, None),
+										module::LibraryItemStatusSignals::Crashed(guid, info) => self.item_closed(uuid, &guid, Some(info)),
+										module::LibraryItemStatusSignals::UninstallFailed(guid, reason) => ev::send_item_error(&guid, reason, uuid, &mut self.connections),
+										module::LibraryItemStatusSignals::Installed(guid) => {
+											match self.core.library.replace_state(uuid, &guid, &[library::LibraryItemStatus::Installing, library::LibraryItemStatus::NotInstalled], Some(library::LibraryItemStatus::Installed)) {
+												Ok(()) => ev::send_updated_item(&self.core.library, &guid, frontend::Topic::ItemStatus, uuid, &mut self.connections),
+												Err(e) => eprintln!("Failed marking item as installed: {}", e)
+											}
+										},
+										module::LibraryItemStatusSignals::InstallFailed(guid, reason) => {
+											match self.core.library.replace_state(uuid, &guid, &[library::LibraryItemStatus::Installing], Some(library::LibraryItemStatus::NotInstalled)) {
+												Ok(()) => ev::send_updated_item(&self.core.library, &guid, frontend::Topic::ItemStatus, uuid, &mut self.connections),
+												Err(e) => eprintln!("Failed resetting status of item: {}", e)
+											}
+											ev::send_item_error(&guid, reason, uuid, &mut self.connections);
+										}
 									};
 								},
 								module::ModuleCommands::PreferenceDiagResponse(itype, diag) => {
 									let gt = (uuid.to_string(), itype);
 									if let Some((recv, muuid)) = self.core.open_preferences.remove(&gt) {
 										let diag = frontend::CoreEvent::new(frontend::CoreActions::PreferenceDiagResponse(gt, diag), muuid);
-										result_printer!(self.connections.write_stream(&recv, diag).await, "Failed writing to FE"); //TODO: Don't block
+										result_printer!(self.connections.write_stream(&recv, diag), "Failed writing to FE");
+									}
+								},
+								module::ModuleCommands::RemoveLibraryItem(guid) => {
+									match self.core.library.remove(uuid, &guid) {
+										Ok(change) => ev::send_item_changes(&self.core.library, &[change], uuid, &mut self.connections),
+										Err(e) => eprintln!("Error removing an item from library: {}", e)
+									}
+								},
+								module::ModuleCommands::UpdateLibraryItem(patch) => {
+									match self.core.library.update(uuid, patch) {
+										Ok(change) => ev::send_item_changes(&self.core.library, &[change], uuid, &mut self.connections),
+										Err(e) => eprintln!("Error updating an item of library: {}", e)
+									}
+								},
+								module::ModuleCommands::ReplaceAllItems(items) => {
+									match self.core.library.replace_all(uuid, items, &self.mods.mod_mgr) {
+										Ok(changes) => ev::send_item_changes(&self.core.library, &changes, uuid, &mut self.connections),
+										Err(e) => eprintln!("Error replacing items of library: {}", e)
+									}
+								},
+								module::ModuleCommands::Progress(progress) => {
+									let operation = progress.operation.clone();
+									match self.core.operations.update(uuid, progress.clone()) {
+										Forward::Now => ev::send_progress(uuid, progress, &mut self.connections),
+										Forward::Later(delay) => {
+											self.core.internal_futures.push(tokio::spawn(async move {
+												tokio::time::sleep(delay).await;
+												InternalCoreFutures::FlushProgress(uuid, operation)
+											}));
+										},
+										Forward::Skip => ()
 									}
 								}
 							}
@@ -260,11 +716,16 @@ impl EventLoop {
 				// Module handle died
 				death = self.mods.futures.select_next_some() => {
 					match death {
-						Ok(safe) => {
-							match safe.1 {
-								Ok(excuse) => eprintln!("The module {} died with an excuse: {:?}", safe.0, excuse),
-								Err(e) => eprintln!("The module {} died due to an error: {}", safe.0, e)
+						// unloaded modules are supposed to return
+						Ok(safe) if !self.mods.mod_mgr.modules.contains_key(safe.0) => println!("The module {} stopped", safe.0),
+						Ok((module, res)) => {
+							match &res {
+								Ok(excuse) => eprintln!("The module {} died with an excuse: {:?}", module, excuse),
+								Err(e) => eprintln!("The module {} died due to an error: {}", module, e)
 							}
+							ev::send_module_state(module, frontend::ModuleState::Crashed, &mut self.connections);
+							let (state, restart) = Self::decision_state(self.mods.supervisor.died(module, res.as_ref().ok()));
+							result_printer!(self.unload_module(module, state, restart), "Failed unloading dead module");
 						},
 						Err(e) => eprintln!("A module crashed: {}", e)
 					}
@@ -273,9 +734,11 @@ impl EventLoop {
 				complete => break
 			}
 		}
-		unsafe {
-			// try gracefully killing redis
-			libc::kill(self.db_pid as i32, libc::SIGINT);
+		if let Some(db_pid) = self.db_pid {
+			unsafe {
+				// try gracefully killing redis
+				libc::kill(db_pid as i32, libc::SIGINT);
+			}
 		}
 	}
 }
@@ -0,0 +1,76 @@
+//! Framing of messages on the frontend socket
+//!
+//! Every message, in both directions, is sent as a frame: the length
+//! of the payload as a big-endian u32, followed by the payload itself.
+//! All payloads are JSON, including the handshake.
+use crate::errors::FrameTooLargeError;
+
+/// bytes used for the length prefix of a frame
+pub const LENGTH_SIZE: usize = 4;
+/// default for the largest payload accepted or sent, 16 MiB
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// prefixes payload with its length
+pub fn encode(payload: &[u8], max: usize) -> Result<Vec<u8>, FrameTooLargeError> {
+	if payload.len() > max || payload.len() > u32::MAX as usize {
+		return Err(FrameTooLargeError::new(payload.len(), max));
+	}
+	let mut frame = Vec::with_capacity(LENGTH_SIZE + payload.len());
+	frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+	frame.extend_from_slice(payload);
+	Ok(frame)
+}
+
+/// reads the payload length from a frame header
+pub fn decode_length(header: [u8; LENGTH_SIZE]) -> usize {
+	u32::from_be_bytes(header) as usize
+}
+
+/// splits a stream of bytes into frames
+///
+/// Frames larger than the maximum are skipped without being buffered,
+/// the connection can go on with the next frame.
+pub struct FrameDecoder {
+	buf: Vec<u8>,
+	max: usize,
+	/// bytes of an oversized frame still to be skipped
+	discard: usize
+}
+impl FrameDecoder {
+	pub fn new(max: usize) -> Self {
+		FrameDecoder {
+			buf: Vec::new(),
+			max,
+			discard: 0
+		}
+	}
+
+	/// adds bytes read from the socket
+	pub fn extend(&mut self, bytes: &[u8]) {
+		let skip = self.discard.min(bytes.len());
+		self.discard -= skip;
+		self.buf.extend_from_slice(&bytes[skip..]);
+	}
+
+	/// returns the next complete frame, None if more bytes are needed
+	pub fn next_frame(&mut self) -> Option<Result<Vec<u8>, FrameTooLargeError>> {
+		if self.discard > 0 || self.buf.len() < LENGTH_SIZE {
+			return None;
+		}
+		let mut header = [0; LENGTH_SIZE];
+		header.copy_from_slice(&self.buf[..LENGTH_SIZE]);
+		let len = decode_length(header);
+		if len > self.max {
+			let available = self.buf.len() - LENGTH_SIZE;
+			self.discard = len.saturating_sub(available);
+			self.buf.drain(..LENGTH_SIZE + len.min(available));
+			return Some(Err(FrameTooLargeError::new(len, self.max)));
+		}
+		if self.buf.len() < LENGTH_SIZE + len {
+			return None;
+		}
+		let frame = self.buf[LENGTH_SIZE..LENGTH_SIZE + len].to_vec();
+		self.buf.drain(..LENGTH_SIZE + len);
+		Some(Ok(frame))
+	}
+}
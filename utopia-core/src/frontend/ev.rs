@@ -1,13 +1,80 @@
-use utopia_common::frontend;
+use utopia_common::{frontend::{self, Topic},
+                    library,
+                    module::Progress};
 
-use crate::{core::Library, frontend::SockStreamMap, result_printer};
+use crate::{core::{ItemChange, Library},
+            frontend::{subscriptions::Scope, SockStreamMap},
+            result_printer};
 
-pub async fn send_updated_item(library: &Library, uuid: &String, connections: &mut SockStreamMap) {
+/// sends the item to frontends subscribed to topic, module is the one
+/// the change originates from
+pub fn send_updated_item(library: &Library, uuid: &String, topic: Topic, module: &str, connections: &mut SockStreamMap) {
 	match library.get(uuid) {
 		Ok(item) => {
 			let details = frontend::CoreEvent::new(frontend::CoreActions::ResponseGameUpdate(item.to_frontend()), None);
-			result_printer!(connections.broadcast_stream(details).await, "Failed writing to FE");
+			let scope = Scope::new(topic).item(uuid).module(module);
+			result_printer!(connections.publish(scope, details), "Failed writing to FE");
 		},
 		Err(e) => eprintln!("Utopia Error: {}", e)
 	};
 }
+
+pub fn send_removed_item(uuid: &str, module: &str, connections: &mut SockStreamMap) {
+	let removed = frontend::CoreEvent::new(frontend::CoreActions::SignalItemRemoved(uuid.to_string()), None);
+	let scope = Scope::new(Topic::Library).item(uuid).module(module);
+	result_printer!(connections.publish(scope, removed), "Failed writing to FE");
+}
+
+pub fn send_item_changes(library: &Library, changes: &[ItemChange], module: &str, connections: &mut SockStreamMap) {
+	for change in changes {
+		match change {
+			ItemChange::Updated(uuid) => send_updated_item(library, uuid, Topic::Library, module, connections),
+			ItemChange::Removed(uuid) => send_removed_item(uuid, module, connections)
+		}
+	}
+}
+
+pub fn send_progress(module: &str, progress: Progress, connections: &mut SockStreamMap) {
+	let item = progress.item.clone();
+	let progress = frontend::CoreEvent::new(frontend::CoreActions::SignalProgress((module.to_string(), progress)), None);
+	let mut scope = Scope::new(Topic::Progress).module(module);
+	if let Some(uuid) = &item {
+		scope = scope.item(uuid);
+	}
+	result_printer!(connections.publish(scope, progress), "Failed writing to FE");
+}
+
+/// tells frontends an update, install or uninstall of the item failed
+pub fn send_item_error(uuid: &str, reason: String, module: &str, connections: &mut SockStreamMap) {
+	let error = frontend::CoreEvent::new(frontend::CoreActions::SignalItemError(uuid.to_string(), reason), None);
+	let scope = Scope::new(Topic::ItemStatus).item(uuid).module(module);
+	result_printer!(connections.publish(scope, error), "Failed writing to FE");
+}
+
+pub fn send_crash(uuid: &str, crash: library::Crash, module: &str, connections: &mut SockStreamMap) {
+	let crashed = frontend::CoreEvent::new(frontend::CoreActions::SignalItemCrashed(uuid.to_string(), crash), None);
+	let scope = Scope::new(Topic::Processes).item(uuid).module(module);
+	result_printer!(connections.publish(scope, crashed), "Failed writing to FE");
+}
+
+pub fn send_module_state(module: &str, state: frontend::ModuleState, connections: &mut SockStreamMap) {
+	let state = frontend::CoreEvent::new(frontend::CoreActions::SignalModuleState(module.to_string(), state), None);
+	result_printer!(connections.publish(Scope::new(Topic::Modules).module(module), state), "Failed writing to FE");
+}
+
+pub fn send_refresh(module: &str, connections: &mut SockStreamMap) {
+	let refresh = frontend::CoreEvent::new(frontend::CoreActions::SignalRefresh(module.to_string()), None);
+	result_printer!(connections.publish(Scope::new(Topic::Library).module(module), refresh), "Failed writing to FE");
+}
+
+pub fn send_preferences_changed(
+	module: &str,
+	diag: frontend::library::preferences::DiagType,
+	connections: &mut SockStreamMap
+) {
+	let changed = frontend::CoreEvent::new(
+		frontend::CoreActions::SignalPreferencesChanged((module.to_string(), diag)),
+		None
+	);
+	result_printer!(connections.publish(Scope::new(Topic::Preferences).module(module), changed), "Failed writing to FE");
+}
@@ -1,94 +1,297 @@
+pub mod codec;
 pub mod ev;
 pub mod socket;
+pub mod subscriptions;
+pub mod writer;
 //pub mod con;
-use std::{collections::HashMap, error::Error, pin::Pin};
+use std::{collections::HashMap, error::Error, io, pin::Pin, time::Duration};
 
 use futures::{stream::{FusedStream, Stream},
               task::{Context, Poll}};
 use serde_json;
-use tokio::{io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
-            net::UnixStream};
+use tokio::{io::{AsyncRead, AsyncReadExt, AsyncWriteExt, ReadBuf},
+            net::{unix::OwnedReadHalf, UnixStream},
+            sync::mpsc::error::TrySendError};
 use utopia_common::frontend;
 
-use crate::errors::FrontendNotAvailableError;
+use self::{codec::FrameDecoder,
+           subscriptions::{Scope, Subscriptions},
+           writer::Outbound};
+use crate::{config::{DuplicateNamePolicy, SlowConsumerPolicy, UtopiaFrontendConfig},
+            errors::{FrameTooLargeError,
+                     FrontendNotAvailableError,
+                     HandshakeError,
+                     ProtocolVersionError,
+                     SlowFrontendError},
+            modules::manifest::CORE_VERSION};
+
+/// optional protocol features frontends can request in their
+/// handshake
+pub const FEATURES: &[&str] = &[];
+
+/// a frontend that completed the handshake
+#[derive(Debug)]
+pub struct FrontendInfo {
+	pub name: String,
+	pub client_version: String,
+	pub protocol_version: frontend::ProtocolVersion,
+	/// the requested features µCore supports
+	pub features: Vec<String>
+}
+impl FrontendInfo {
+	/// picks the newest protocol version both sides speak, Err is the
+	/// reason to reject the frontend
+	fn negotiate(handshake: frontend::Handshake) -> Result<Self, String> {
+		// thanks to @APerson and @JayDepp on SO on for this whitespace filter
+		// https://stackoverflow.com/a/57063944/10890264
+		let name: String = handshake.name.chars().filter(|c| !c.is_whitespace()).collect();
+		if name.is_empty() {
+			return Err(String::from("the name must not be empty"));
+		}
+		let protocol_version = frontend::PROTOCOL_VERSIONS
+			.iter()
+			.rev()
+			.find(|v| handshake.protocol_versions.contains(v))
+			.copied()
+			.ok_or_else(|| {
+				format!(
+					"no common protocol version, the frontend speaks {:?}, µCore {:?}",
+					handshake.protocol_versions,
+					frontend::PROTOCOL_VERSIONS
+				)
+			})?;
+		Ok(FrontendInfo {
+			name,
+			client_version: handshake.client_version,
+			protocol_version,
+			features: handshake
+				.features
+				.into_iter()
+				.filter(|f| FEATURES.contains(&f.as_str()))
+				.collect()
+		})
+	}
+}
 
 pub struct SocketStream {
-	inner: UnixStream,
+	inner: OwnedReadHalf,
+	outbound: Outbound,
+	decoder: FrameDecoder,
+	info: FrontendInfo,
+	subscriptions: Subscriptions,
 	terminated: bool
 }
-impl Stream for SocketStream {
-	type Item = Result<frontend::FrontendEvent, Box<dyn Error>>;
+impl SocketStream {
+	pub fn info(&self) -> &FrontendInfo {
+		&self.info
+	}
 
-	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-		let mut buf = [0; 0xFFFF];
-		let mut reader = ReadBuf::new(&mut buf);
-		let stream = Pin::new(&mut self.inner);
-		match stream.poll_read(cx, &mut reader) {
-			Poll::Ready(Ok(())) => match reader.filled().len() {
-				0 => {
+	/// queues frame to be written to the frontend
+	fn send(&mut self, frame: Vec<u8>, slow_consumers: SlowConsumerPolicy) -> Result<(), Box<dyn Error>> {
+		match self.outbound.try_send(frame) {
+			Ok(()) => Ok(()),
+			Err(TrySendError::Full(_)) => match slow_consumers {
+				SlowConsumerPolicy::Disconnect => {
+					self.outbound.abort();
 					self.terminated = true;
-					Poll::Ready(None)
+					Err(Box::new(SlowFrontendError::new(&self.info.name, true)))
 				},
-				_ => match serde_json::from_slice(reader.filled()) {
-					Ok(action) => Poll::Ready(Some(Ok(action))),
-					Err(e) => Poll::Ready(Some(Err(Box::new(e))))
-				}
+				SlowConsumerPolicy::DropMessages => Err(Box::new(SlowFrontendError::new(&self.info.name, false)))
 			},
-			Poll::Ready(Err(e)) => Poll::Ready(Some(Err(Box::new(e)))),
-			Poll::Pending => Poll::Pending
+			Err(TrySendError::Closed(_)) => {
+				self.terminated = true;
+				Err(Box::new(FrontendNotAvailableError::new(&self.info.name)))
+			}
 		}
 	}
 }
-impl AsyncWrite for SocketStream {
-	fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, std::io::Error>> {
-		let stream = Pin::new(&mut self.inner);
-		stream.poll_write(cx, buf)
-	}
-
-	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Result<(), std::io::Error>> {
-		let stream = Pin::new(&mut self.inner);
-		stream.poll_flush(cx)
-	}
+impl Stream for SocketStream {
+	type Item = Result<frontend::FrontendEvent, Box<dyn Error>>;
 
-	fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Result<(), std::io::Error>> {
-		let stream = Pin::new(&mut self.inner);
-		stream.poll_shutdown(cx)
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+		if self.terminated {
+			return Poll::Ready(None);
+		}
+		// the writer only stops on its own if the frontend is gone or
+		// didn't read for too long
+		if let Poll::Ready(res) = self.outbound.poll_finished(cx) {
+			if let Err(e) = res {
+				eprintln!("Failed writing to FE {}: {}", self.info.name, e);
+			}
+			self.terminated = true;
+			return Poll::Ready(None);
+		}
+		let mut buf = [0; 0xFFFF];
+		loop {
+			match self.decoder.next_frame() {
+				Some(Ok(frame)) => {
+					return match serde_json::from_slice::<frontend::FrontendEvent>(&frame) {
+						Ok(action) if action.version != self.info.protocol_version => Poll::Ready(Some(Err(Box::new(
+							ProtocolVersionError::new(action.version, self.info.protocol_version)
+						)))),
+						Ok(action) if action.action.since() > self.info.protocol_version => Poll::Ready(Some(Err(
+							Box::new(ProtocolVersionError::new(action.action.since(), self.info.protocol_version))
+						))),
+						Ok(action) => Poll::Ready(Some(Ok(action))),
+						Err(e) => Poll::Ready(Some(Err(Box::new(e))))
+					}
+				},
+				Some(Err(e)) => return Poll::Ready(Some(Err(Box::new(e)))),
+				None => ()
+			}
+			let mut reader = ReadBuf::new(&mut buf);
+			let stream = Pin::new(&mut self.inner);
+			match stream.poll_read(cx, &mut reader) {
+				Poll::Ready(Ok(())) => match reader.filled().len() {
+					0 => {
+						self.terminated = true;
+						return Poll::Ready(None);
+					},
+					_ => self.decoder.extend(reader.filled())
+				},
+				Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(Box::new(e)))),
+				Poll::Pending => return Poll::Pending
+			}
+		}
 	}
 }
 
+/// connected frontends, keyed by the connection id µCore assigned
 pub struct SockStreamMap {
-	inner: HashMap<String, SocketStream>
+	inner: HashMap<String, SocketStream>,
+	max_frame_size: usize,
+	duplicate_names: DuplicateNamePolicy,
+	queue_size: usize,
+	write_timeout: Duration,
+	slow_consumers: SlowConsumerPolicy,
+	next_id: u64
 }
 impl SockStreamMap {
-	pub fn new() -> Self {
+	pub fn new(config: &UtopiaFrontendConfig) -> Self {
 		Self {
-			inner: HashMap::new()
+			inner: HashMap::new(),
+			max_frame_size: config.max_frame_size,
+			duplicate_names: config.duplicate_names,
+			queue_size: config.queue_size,
+			write_timeout: Duration::from_secs(config.write_timeout),
+			slow_consumers: config.slow_consumers,
+			next_id: 0
 		}
 	}
 
-	pub async fn accept_handshake(mut stream: UnixStream) -> Result<(String, UnixStream), Box<dyn Error>> {
-		stream.readable().await?;
-		let mut name: Vec<u8> = vec![0; 0o100];
-		let n = stream.read(&mut name).await?;
-		name.truncate(n);
-		let name = std::str::from_utf8(&name)?;
-		// thanks to @APerson and @JayDepp on SO on for this whitespace filter
-		// https://stackoverflow.com/a/57063944/10890264
-		Ok((name.chars().filter(|c| !c.is_whitespace()).collect(), stream))
+	pub fn max_frame_size(&self) -> usize {
+		self.max_frame_size
+	}
+
+	/// reads the [Handshake](frontend::Handshake) of a new frontend,
+	/// which is the first frame it sends. Frontends that can't be
+	/// served are told why before the connection is closed.
+	pub async fn accept_handshake(mut stream: UnixStream, max_frame_size: usize) -> Result<(FrontendInfo, UnixStream), Box<dyn Error>> {
+		let mut header = [0; codec::LENGTH_SIZE];
+		stream.read_exact(&mut header).await?;
+		let len = codec::decode_length(header);
+		if len > max_frame_size {
+			return Err(Box::new(FrameTooLargeError::new(len, max_frame_size)));
+		}
+		let mut frame: Vec<u8> = vec![0; len];
+		stream.read_exact(&mut frame).await?;
+		let negotiated = serde_json::from_slice(&frame)
+			.map_err(|e| format!("invalid handshake: {}", e))
+			.and_then(FrontendInfo::negotiate);
+		match negotiated {
+			Ok(info) => Ok((info, stream)),
+			Err(reason) => {
+				Self::reject(stream, frontend::PROTOCOL_VERSION, &reason, max_frame_size).await?;
+				Err(Box::new(HandshakeError::new(reason)))
+			}
+		}
 	}
 
-	pub async fn insert(&mut self, name: String, stream: UnixStream) -> Result<(), Box<dyn Error>> {
+	/// tells a frontend why it's rejected, the connection is closed
+	/// afterwards
+	async fn reject(
+		mut stream: UnixStream,
+		version: frontend::ProtocolVersion,
+		reason: &str,
+		max_frame_size: usize
+	) -> io::Result<()> {
+		let rejected = frontend::CoreEvent {
+			version,
+			uuid: None,
+			action: frontend::CoreActions::SignalHandshakeRejected(frontend::HandshakeRejected {
+				reason: reason.to_owned(),
+				protocol_versions: frontend::PROTOCOL_VERSIONS.to_vec()
+			})
+		};
+		let frame = codec::encode(&serde_json::to_vec(&rejected)?, max_frame_size)
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		stream.write_all(&frame).await
+	}
+
+	/// adds a frontend that completed the handshake, under a new
+	/// connection id. A frontend with the name of one that is already
+	/// connected is handled according to the
+	/// [DuplicateNamePolicy].
+	pub fn insert(&mut self, info: FrontendInfo, stream: UnixStream) -> Result<(), Box<dyn Error>> {
+		let duplicates: Vec<String> = self
+			.inner
+			.iter()
+			.filter(|(_, fe)| !fe.terminated && fe.info.name == info.name)
+			.map(|(id, _)| id.to_owned())
+			.collect();
+		if !duplicates.is_empty() {
+			match self.duplicate_names {
+				DuplicateNamePolicy::Reject => {
+					let reason = format!("a frontend named {} is already connected", info.name);
+					let (version, max_frame_size, msg) = (info.protocol_version, self.max_frame_size, reason.clone());
+					tokio::spawn(async move {
+						if let Err(e) = Self::reject(stream, version, &msg, max_frame_size).await {
+							eprintln!("Failed writing to FE: {}", e);
+						}
+					});
+					return Err(Box::new(HandshakeError::new(reason)));
+				},
+				DuplicateNamePolicy::Replace => {
+					for id in duplicates {
+						// dropping the stream closes the connection once
+						// its queue is written
+						self.inner.remove(&id);
+						println!("FE Connection {} replaced by a new connection", id);
+					}
+				},
+				DuplicateNamePolicy::Allow => ()
+			}
+		}
+
+		let id = format!("{}#{}", info.name, self.next_id);
+		self.next_id += 1;
 		let success = frontend::CoreEvent {
-			version: String::from("0.0.0"),
+			version: info.protocol_version,
 			uuid: None,
-			action: frontend::CoreActions::SignalSuccessHandshake(name.clone())
+			action: frontend::CoreActions::SignalSuccessHandshake(frontend::HandshakeAccepted {
+				connection_id: id.clone(),
+				name: info.name.clone(),
+				core_version: String::from(CORE_VERSION),
+				protocol_version: info.protocol_version,
+				features: info.features.clone()
+			})
 		};
-		stream.writable().await?;
-		stream.try_write(&serde_json::to_vec(&success)?)?;
-		self.inner.insert(name, SocketStream {
-			inner: stream,
+		let frame = codec::encode(&serde_json::to_vec(&success)?, self.max_frame_size)?;
+		println!(
+			"Frontend {} {} connected as {} using protocol version {}",
+			info.name, info.client_version, id, info.protocol_version
+		);
+		let (read, write) = stream.into_split();
+		let mut stream = SocketStream {
+			inner: read,
+			outbound: Outbound::spawn(write, self.queue_size, self.write_timeout),
+			decoder: FrameDecoder::new(self.max_frame_size),
+			info,
+			subscriptions: Subscriptions::default(),
 			terminated: false
-		});
+		};
+		stream.send(frame, self.slow_consumers)?;
+		self.inner.insert(id, stream);
 		Ok(())
 	}
 
@@ -99,16 +302,61 @@ impl SockStreamMap {
 		}
 	}
 
-	pub async fn write_stream(&mut self, uuid: &String, msg: frontend::CoreEvent) -> Result<(), Box<dyn Error>> {
-		let bytes = serde_json::to_vec(&msg)?;
-		self.get(uuid)?.write_all(&bytes).await?;
+	pub fn subscribe(&mut self, uuid: &String, subscription: frontend::Subscription) -> Result<(), FrontendNotAvailableError> {
+		self.get(uuid)?.subscriptions.subscribe(subscription);
+		Ok(())
+	}
+
+	pub fn unsubscribe(&mut self, uuid: &String, topics: &[frontend::Topic]) -> Result<(), FrontendNotAvailableError> {
+		self.get(uuid)?.subscriptions.unsubscribe(topics);
 		Ok(())
 	}
 
-	pub async fn broadcast_stream(&mut self, msg: frontend::CoreEvent) -> Result<(), Box<dyn Error>> {
-		let bytes = serde_json::to_vec(&msg)?;
-		for stream in self.inner.values_mut() {
-			stream.write_all(&bytes).await?;
+	/// queues msg for a frontend, using its protocol version
+	pub fn write_stream(&mut self, uuid: &String, mut msg: frontend::CoreEvent) -> Result<(), Box<dyn Error>> {
+		let (max_frame_size, slow_consumers) = (self.max_frame_size, self.slow_consumers);
+		let stream = self.get(uuid)?;
+		if stream.terminated {
+			return Err(Box::new(FrontendNotAvailableError::new(uuid)));
+		}
+		if msg.action.since() > stream.info.protocol_version {
+			return Err(Box::new(ProtocolVersionError::new(msg.action.since(), stream.info.protocol_version)));
+		}
+		msg.version = stream.info.protocol_version;
+		msg.action.downgrade(msg.version);
+		stream.send(codec::encode(&serde_json::to_vec(&msg)?, max_frame_size)?, slow_consumers)
+	}
+
+	/// queues msg for every frontend subscribed to scope that knows the
+	/// action. Frontends that can't take it are reported, but don't keep
+	/// the others from getting it.
+	pub fn publish(&mut self, scope: Scope, mut msg: frontend::CoreEvent) -> Result<(), Box<dyn Error>> {
+		let since = msg.action.since();
+		// frontends mostly speak the same version, so the frame is only
+		// encoded again if the version changes. Newer versions come first,
+		// as downgrading drops what older ones don't know.
+		let mut encoded: Option<(frontend::ProtocolVersion, Vec<u8>)> = None;
+		let mut receivers: Vec<_> = self
+			.inner
+			.iter_mut()
+			.filter(|(_, fe)| !fe.terminated && since <= fe.info.protocol_version && fe.subscriptions.matches(&scope))
+			.collect();
+		receivers.sort_by_key(|(_, fe)| std::cmp::Reverse(fe.info.protocol_version));
+		for (id, stream) in receivers {
+			let version = stream.info.protocol_version;
+			let frame = match &encoded {
+				Some((v, frame)) if *v == version => frame.clone(),
+				_ => {
+					msg.version = version;
+					msg.action.downgrade(version);
+					let frame = codec::encode(&serde_json::to_vec(&msg)?, self.max_frame_size)?;
+					encoded = Some((version, frame.clone()));
+					frame
+				}
+			};
+			if let Err(e) = stream.send(frame, self.slow_consumers) {
+				eprintln!("Failed writing to FE {}: {}", id, e);
+			}
 		}
 		Ok(())
 	}
@@ -150,6 +398,6 @@ impl FusedStream for SockStreamMap {
 				term = false;
 			}
 		}
-		return term;
+		term
 	}
 }
@@ -42,7 +42,7 @@ impl FusedStream for UtopiaSocket {
 impl Drop for UtopiaSocket {
 	fn drop(&mut self) {
 		// There's no way to return a useful error here
-		let _ = std::fs::remove_file(&self.path).unwrap();
+		let _ = std::fs::remove_file(&self.path);
 	}
 }
 
@@ -0,0 +1,91 @@
+//! Routing of events to the frontends that subscribed to them
+use std::collections::{HashMap, HashSet};
+
+use utopia_common::frontend::{Subscription, Topic};
+
+/// what an event is about
+#[derive(Debug, Clone, Copy)]
+pub struct Scope<'a> {
+	pub topic: Topic,
+	/// uuid of the library item the event is about
+	pub item: Option<&'a str>,
+	/// id of the module the event is about or originates from
+	pub module: Option<&'a str>
+}
+impl<'a> Scope<'a> {
+	pub fn new(topic: Topic) -> Self {
+		Scope {
+			topic,
+			item: None,
+			module: None
+		}
+	}
+
+	pub fn item(mut self, uuid: &'a str) -> Self {
+		self.item = Some(uuid);
+		self
+	}
+
+	pub fn module(mut self, id: &'a str) -> Self {
+		self.module = Some(id);
+		self
+	}
+}
+
+/// an empty set lets everything through
+#[derive(Debug, Default)]
+struct Filter {
+	items: HashSet<String>,
+	modules: HashSet<String>
+}
+impl Filter {
+	fn matches(&self, scope: &Scope) -> bool {
+		let item = match scope.item {
+			Some(uuid) => self.items.is_empty() || self.items.contains(uuid),
+			None => true
+		};
+		let module = match scope.module {
+			Some(id) => self.modules.is_empty() || self.modules.contains(id),
+			None => true
+		};
+		item && module
+	}
+}
+
+/// topics a frontend receives events of
+#[derive(Debug)]
+pub struct Subscriptions {
+	topics: HashMap<Topic, Filter>
+}
+impl Default for Subscriptions {
+	/// every topic, unfiltered
+	fn default() -> Self {
+		Subscriptions {
+			topics: Topic::ALL.iter().map(|topic| (*topic, Filter::default())).collect()
+		}
+	}
+}
+impl Subscriptions {
+	pub fn subscribe(&mut self, subscription: Subscription) {
+		for topic in subscription.topics {
+			self.topics.insert(topic, Filter {
+				items: subscription.items.iter().cloned().collect(),
+				modules: subscription.modules.iter().cloned().collect()
+			});
+		}
+	}
+
+	pub fn unsubscribe(&mut self, topics: &[Topic]) {
+		match topics.is_empty() {
+			true => self.topics.clear(),
+			false => self.topics.retain(|topic, _| !topics.contains(topic))
+		}
+	}
+
+	pub fn matches(&self, scope: &Scope) -> bool {
+		match self.topics.get(&scope.topic) {
+			Some(filter) => filter.matches(scope),
+			None => false
+		}
+	}
+}
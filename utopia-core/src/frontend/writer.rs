@@ -0,0 +1,77 @@
+//! Writing to frontends in the background
+//!
+//! Every connection owns a task that writes the frames queued for it,
+//! so a frontend that stops reading only fills its own bounded queue
+//! instead of blocking the event loop. What happens once the queue is
+//! full is up to the [SlowConsumerPolicy](crate::config::SlowConsumerPolicy).
+use std::{io,
+          task::{Context, Poll},
+          time::Duration};
+
+use futures::FutureExt;
+use tokio::{io::AsyncWriteExt,
+            net::unix::OwnedWriteHalf,
+            sync::mpsc::{self, error::TrySendError},
+            task::JoinHandle};
+
+pub struct Outbound {
+	queue: mpsc::Sender<Vec<u8>>,
+	task: JoinHandle<io::Result<()>>,
+	finished: bool
+}
+impl Outbound {
+	/// starts writing to stream. A frame that takes longer than timeout
+	/// to write ends the task with an error.
+	pub fn spawn(stream: OwnedWriteHalf, queue_size: usize, timeout: Duration) -> Self {
+		let (queue, frames) = mpsc::channel(queue_size);
+		Outbound {
+			queue,
+			task: tokio::spawn(write_frames(stream, frames, timeout)),
+			finished: false
+		}
+	}
+
+	/// queues a frame without waiting for room in the queue
+	pub fn try_send(&self, frame: Vec<u8>) -> Result<(), TrySendError<Vec<u8>>> {
+		self.queue.try_send(frame)
+	}
+
+	/// stops writing right away, dropping queued frames and closing the
+	/// connection
+	pub fn abort(&mut self) {
+		self.task.abort();
+		self.finished = true;
+	}
+
+	/// resolves once the writer stopped on its own, i.e. because writing
+	/// failed
+	pub fn poll_finished(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		if self.finished {
+			return Poll::Ready(Ok(()));
+		}
+		match self.task.poll_unpin(cx) {
+			Poll::Ready(res) => {
+				self.finished = true;
+				Poll::Ready(res.unwrap_or_else(|e| Err(io::Error::other(e))))
+			},
+			Poll::Pending => Poll::Pending
+		}
+	}
+}
+
+/// writes frames until the queue is closed, which happens once the
+/// connection is dropped
+async fn write_frames(mut stream: OwnedWriteHalf, mut frames: mpsc::Receiver<Vec<u8>>, timeout: Duration) -> io::Result<()> {
+	while let Some(frame) = frames.recv().await {
+		match tokio::time::timeout(timeout, stream.write_all(&frame)).await {
+			Ok(res) => res?,
+			Err(_) => {
+				return Err(io::Error::new(
+					io::ErrorKind::TimedOut,
+					format!("writing a message took longer than {}s", timeout.as_secs())
+				))
+			}
+		}
+	}
+	Ok(())
+}
@@ -1,67 +1,127 @@
+mod cli;
+mod config;
 mod core;
 mod database;
 mod errors;
 mod eventloop;
 pub mod frontend;
 mod modules;
-use std::{env::var as env_var, path::PathBuf};
+mod process;
+use std::{os::unix::io::AsRawFd,
+          path::{Path, PathBuf}};
 
+use clap::Parser;
+
+pub use config::{UtopiaConfiguration, UtopiaDatabaseConfig};
 use eventloop::EventLoop;
 
-#[derive(serde::Serialize)]
-pub struct UtopiaDatabaseConfig {
-	pub tconfig: PathBuf,
-	pub socket: PathBuf,
-	pub inherits: PathBuf,
-	pub logfile: PathBuf,
-	pub working_dir: PathBuf,
-	pub ready_sock: PathBuf
+/// appends stdout and stderr of µCore to path
+fn redirect_output(path: &Path) -> Result<(), errors::FileError> {
+	let file = std::fs::OpenOptions::new()
+		.create(true)
+		.append(true)
+		.open(path)
+		.map_err(|io| errors::FileError::new(path.to_owned(), io))?;
+	for fd in &[libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+		if unsafe { libc::dup2(file.as_raw_fd(), *fd) } < 0 {
+			return Err(errors::FileError::new(path.to_owned(), std::io::Error::last_os_error()));
+		}
+	}
+	Ok(())
 }
 
-pub struct UtopiaConfiguration {
-	pub runtime_dir: PathBuf,
-	pub socket: PathBuf,
-	pub database: UtopiaDatabaseConfig
+/// spawns the µtopia database if configured to do so and connects
+/// to it
+///
+/// returns the database process with its rendered config file, if it
+/// was spawned by µCore
+async fn open_database(
+	config: &UtopiaDatabaseConfig
+) -> anyhow::Result<(Option<(tokio::process::Child, String)>, redis::Client, utopia_module::UDb)> {
+	let process = match config.spawn {
+		true => Some(database::spawn(config).await?),
+		false => None
+	};
+	let database = redis::Client::open(config.connection_url())?;
+	let shared_db_connection = std::sync::Arc::new(std::sync::RwLock::new(database.get_connection()?));
+	Ok((process, database, shared_db_connection))
 }
 
-impl UtopiaConfiguration {
-	pub fn new() -> Self {
-		let runtime_dir = env_var("XDG_RUNTIME_DIR").expect("XDG_RUNTIME_DIR is not set");
-		let home_dir = env_var("HOME").unwrap_or(unsafe {
-			let passwd: Option<&libc::passwd> = libc::getpwuid(libc::getuid()).as_ref();
-			passwd
-				.map(|v| std::ffi::CStr::from_ptr(v.pw_dir).to_string_lossy().into_owned())
-				.expect("Failed to get home dir, try setting HOME")
-		});
-		let xdg_data = env_var("XDG_DATA_HOME").unwrap_or(format!("{}/.local/share", home_dir));
-		let data_dir = format!("{}/utopia", xdg_data);
-		UtopiaConfiguration {
-			socket: format!("{}/utopia.sock", runtime_dir).into(),
-			database: UtopiaDatabaseConfig {
-				tconfig: PathBuf::from("/home/admin/workspace/core/µCore/db.conf.in"),
-				socket: format!("{}/utopiadb.sock", runtime_dir).into(),
-				inherits: PathBuf::from("/home/admin/workspace/core/µCore/default.conf"),
-				logfile: format!("{}/db.log", data_dir).into(),
-				working_dir: data_dir.into(),
-				ready_sock: format!("{}/_utopiadbctl.dsock", runtime_dir).into()
-			},
-			runtime_dir: runtime_dir.into()
+async fn check_modules(config: &UtopiaConfiguration, files: Vec<PathBuf>) -> anyhow::Result<i32> {
+	let files = match files.is_empty() {
+		true => modules::find_libraries(&config.modules.search_paths),
+		false => files
+	};
+	let (process, _database, shared_db_connection) = open_database(&config.database).await?;
+	let failed = modules::ModuleCore::check(&files, shared_db_connection, config.database.connection_url()).await;
+	println!("{} of {} modules failed to load", failed, files.len());
+
+	if let Some((mut child, tmp_path)) = process {
+		if let Some(pid) = child.id() {
+			unsafe {
+				libc::kill(pid as i32, libc::SIGINT);
+			}
 		}
+		let _ = child.wait().await;
+		let _ = std::fs::remove_file(tmp_path);
 	}
+	Ok(if failed == 0 { 0 } else { 1 })
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-	let config = UtopiaConfiguration::new();
-	let (child, tmp_path) = database::spawn(&config.database).await?;
-	let database = redis::Client::open(format!("unix://{}", config.database.socket.to_string_lossy()))?;
-	let shared_db_connection = std::sync::Arc::new(std::sync::RwLock::new(database.get_connection()?));
+async fn run(config: UtopiaConfiguration) -> anyhow::Result<()> {
+	match &config.source {
+		Some(path) => println!("Using configuration from {}", path.to_string_lossy()),
+		None => println!("No configuration file found, using defaults")
+	}
+	let (process, database, shared_db_connection) = open_database(&config.database).await?;
+	let (child, tmp_path) = match process {
+		Some((child, tmp_path)) => (Some(child), Some(tmp_path)),
+		None => (None, None)
+	};
 
-	let (mods, receiver) = modules::ModuleCore::new(shared_db_connection.clone())?;
+	let (mods, receiver) = modules::ModuleCore::new(
+		shared_db_connection.clone(),
+		config.database.connection_url(),
+		&config.modules
+	)
+	.await?;
 	mods.get_modules();
 	//let (thread_futures, receiver) = mods.spawn_modules();
 	let mut evl = EventLoop::new(config, mods, receiver, child, (database, shared_db_connection));
 	evl.run().await;
-	let _ = std::fs::remove_file(tmp_path);
+	if let Some(tmp_path) = tmp_path {
+		let _ = std::fs::remove_file(tmp_path);
+	}
 	Ok(())
 }
+
+fn main() -> anyhow::Result<()> {
+	let args = cli::Args::parse();
+	let config = args.configuration().map_err(|e| anyhow::anyhow!("{}", e))?;
+	if args.print_config {
+		print!("{}", toml::to_string(&config)?);
+		return Ok(());
+	}
+
+	if let Some(cli::Command::CheckModules {
+		files
+	}) = args.command
+	{
+		let runtime = tokio::runtime::Runtime::new()?;
+		// module threads can't be stopped, so don't wait for them
+		// when exiting
+		std::process::exit(runtime.block_on(check_modules(&config, files))?);
+	}
+
+	// daemonizing forks, so it has to happen before the runtime
+	// spawns any threads
+	if !args.foreground && unsafe { libc::daemon(1, 1) } < 0 {
+		return Err(std::io::Error::last_os_error().into());
+	}
+	match (&config.logging.file, args.foreground) {
+		(Some(logfile), _) => redirect_output(logfile)?,
+		(None, false) => redirect_output(Path::new("/dev/null"))?,
+		(None, true) => ()
+	}
+	tokio::runtime::Runtime::new()?.block_on(run(config))
+}
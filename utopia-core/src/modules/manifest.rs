@@ -0,0 +1,165 @@
+//! Module manifests
+//!
+//! A manifest is a small toml file next to a module's library
+//! (`libfoo.so` → `libfoo.module.toml` or `foo.module.toml`) that
+//! describes the module without having to load its code:
+//!
+//! ```toml
+//! id = "com.github.projekt-utopia.sample_module"
+//! version = "0.1.0"
+//! abi_version = "6.1"
+//! core_version = ">=0.0.1"
+//! dependencies = ["com.github.projekt-utopia.steam"]
+//! optional_dependencies = []
+//! capabilities = ["library", "launch", "preferences"]
+//! ```
+use std::{collections::HashMap,
+          path::{Path, PathBuf}};
+
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+pub use utopia_module::InterfaceVersion;
+
+use super::modules::ModuleKind;
+use crate::errors::{FileError, ManifestError};
+
+/// version of µCore, compared against
+/// [core_version](ModuleManifest::core_version)
+pub const CORE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModuleCapability {
+	/// provides library items
+	Library,
+	Launch,
+	Install,
+	Update,
+	Uninstall,
+	Preferences,
+	/// capabilities of newer modules µCore doesn't know about
+	#[serde(other)]
+	Unknown
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModuleManifest {
+	pub id: String,
+	pub version: Version,
+	/// module interface version as `major.minor`
+	pub abi_version: String,
+	/// requirement on the version of µCore, like `>=0.1, <0.3`
+	#[serde(default = "VersionReq::default")]
+	pub core_version: VersionReq,
+	/// modules that have to be loaded before this one
+	#[serde(default)]
+	pub dependencies: Vec<String>,
+	/// modules that are loaded before this one, if available
+	#[serde(default)]
+	pub optional_dependencies: Vec<String>,
+	#[serde(default)]
+	pub capabilities: Vec<ModuleCapability>,
+	/// library the manifest belongs to
+	#[serde(skip)]
+	pub library: PathBuf
+}
+
+impl ModuleManifest {
+	/// possible manifest locations of the library at path
+	fn paths_for(library: &Path) -> Vec<PathBuf> {
+		let stem = library.file_stem().unwrap_or_default().to_string_lossy();
+		let mut paths = vec![library.with_file_name(format!("{}.module.toml", stem))];
+		if let Some(stripped) = stem.strip_prefix("lib") {
+			paths.push(library.with_file_name(format!("{}.module.toml", stripped)));
+		}
+		paths
+	}
+
+	/// reads and validates the manifest of the library at path.
+	/// Returns Ok(None) if the library has no manifest.
+	pub fn for_library(library: &Path) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+		let path = match Self::paths_for(library).into_iter().find(|p| p.exists()) {
+			Some(path) => path,
+			None => return Ok(None)
+		};
+		let content = std::fs::read_to_string(&path).map_err(|io| FileError::new(path.clone(), io))?;
+		let mut manifest: ModuleManifest =
+			toml::from_str(&content).map_err(|e| ManifestError::new(path.clone(), e.to_string()))?;
+		manifest.library = library.to_owned();
+		manifest.validate().map_err(|msg| ManifestError::new(path, msg))?;
+		Ok(Some(manifest))
+	}
+
+	fn validate(&self) -> Result<(), String> {
+		if self.id.is_empty() {
+			return Err(String::from("id must not be empty"));
+		}
+		let abi_version: InterfaceVersion = self.abi_version.parse()?;
+		if let Some(kind) = ModuleKind::of(&self.library).filter(|kind| !kind.accepts(abi_version)) {
+			return Err(format!(
+				"module implements interface version {}, µCore accepts {}",
+				abi_version,
+				kind.accepted_versions()
+			));
+		}
+		let core = Version::parse(CORE_VERSION).expect("CARGO_PKG_VERSION is valid semver");
+		if !self.core_version.matches(&core) {
+			return Err(format!(
+				"module requires µCore {}, this is {}",
+				self.core_version, CORE_VERSION
+			));
+		}
+		if self.dependencies.contains(&self.id) || self.optional_dependencies.contains(&self.id) {
+			return Err(String::from("module depends on itself"));
+		}
+		Ok(())
+	}
+}
+
+/// sorts manifests so that every module comes after its
+/// dependencies
+///
+/// dependencies without a manifest are left to be checked when
+/// loading, as modules don't need one. Modules that are part of a
+/// dependency cycle, or whose id is taken already, are returned
+/// separately with the reason.
+pub fn load_order(manifests: Vec<ModuleManifest>) -> (Vec<ModuleManifest>, Vec<(ModuleManifest, String)>) {
+	let mut pending: HashMap<String, ModuleManifest> = HashMap::new();
+	let mut rejected = Vec::new();
+	for manifest in manifests {
+		if pending.contains_key(&manifest.id) {
+			let msg = format!("module {} is provided by another library as well", manifest.id);
+			rejected.push((manifest, msg));
+		} else {
+			pending.insert(manifest.id.clone(), manifest);
+		}
+	}
+
+	let mut ordered = Vec::with_capacity(pending.len());
+	while !pending.is_empty() {
+		let mut ready: Vec<String> = pending
+			.values()
+			.filter(|m| {
+				m.dependencies
+					.iter()
+					.chain(m.optional_dependencies.iter())
+					.all(|dep| !pending.contains_key(dep))
+			})
+			.map(|m| m.id.clone())
+			.collect();
+		if ready.is_empty() {
+			// whatever is left depends on each other
+			for (_, manifest) in pending.drain() {
+				rejected.push((manifest, String::from("dependency cycle")));
+			}
+			break;
+		}
+		// keep the order stable between runs
+		ready.sort();
+		for id in ready {
+			ordered.push(pending.remove(&id).unwrap());
+		}
+	}
+	(ordered, rejected)
+}
@@ -1,55 +1,237 @@
+pub mod manifest;
+#[allow(clippy::module_inception)]
 pub mod modules;
+pub mod remote;
+pub mod supervisor;
+use std::{collections::HashSet,
+          os::unix::fs::PermissionsExt,
+          path::{Path, PathBuf}};
+
 use futures::{channel::mpsc, stream};
 use utopia_common::module;
 pub use modules::ThreadHandle;
 
+use manifest::ModuleManifest;
+
+use crate::{config::UtopiaModulesConfig,
+            errors::{ManifestError, ModuleABIError, ModuleDisabledError, NotAModuleError}};
+
+/// lists every shared object and module executable within the given
+/// directories
+///
+/// directories that don't exist are skipped silently. Whether a
+/// library actually is a module is only known after loading it.
+/// Executables named `*.module` are spawned as
+/// [out-of-process](remote) modules.
+pub fn find_libraries<P: AsRef<Path>>(search_paths: &[P]) -> Vec<PathBuf> {
+	let mut libraries = Vec::new();
+	for dir in search_paths {
+		let entries = match std::fs::read_dir(dir) {
+			Ok(entries) => entries,
+			Err(_) => continue
+		};
+		let mut found: Vec<PathBuf> = entries
+			.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+			.filter(|path| {
+				path.is_file() &&
+					match modules::ModuleKind::of(path) {
+						Some(modules::ModuleKind::Library) => true,
+						Some(modules::ModuleKind::Process) => is_executable(path),
+						None => false
+					}
+			})
+			.collect();
+		found.sort();
+		libraries.append(&mut found);
+	}
+	libraries
+}
+
+fn is_executable(path: &Path) -> bool {
+	path.metadata().map(|meta| meta.permissions().mode() & 0o111 != 0).unwrap_or(false)
+}
+
+fn dependencies_of(manifest: Option<&ModuleManifest>) -> Vec<String> {
+	manifest
+		.map(|m| m.dependencies.iter().chain(m.optional_dependencies.iter()).cloned().collect())
+		.unwrap_or_default()
+}
+
+/// libraries in the order they are loaded in, with their manifest
+pub type LoadPlan = Vec<(PathBuf, Option<ModuleManifest>)>;
+
+/// reads the manifests of the given libraries and puts them into
+/// the order they have to be loaded in
+///
+/// libraries without a manifest come after the ones with, unless the
+/// configuration requires one, followed by the ones depending on them.
+/// Libraries that can't be loaded are returned separately with the
+/// reason.
+pub fn load_plan(
+	libraries: Vec<PathBuf>,
+	config: &UtopiaModulesConfig
+) -> (LoadPlan, Vec<(PathBuf, String)>) {
+	let mut manifests = Vec::new();
+	let mut unmanifested = Vec::new();
+	let mut failed = Vec::new();
+	for path in libraries {
+		let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+		let file_stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+		match ModuleManifest::for_library(&path) {
+			Ok(Some(manifest)) => {
+				if config.is_enabled(&[&manifest.id, &file_name, &file_stem]) {
+					manifests.push(manifest);
+				} else {
+					println!("Module {} is disabled, skipping", manifest.id);
+				}
+			},
+			Ok(None) if config.require_manifest => {
+				failed.push((path, String::from("no module manifest found")));
+			},
+			Ok(None) => {
+				if config.is_enabled(&[&file_name, &file_stem]) {
+					unmanifested.push((path, None));
+				} else {
+					println!("Module {} is disabled, skipping", path.to_string_lossy());
+				}
+			},
+			Err(e) => failed.push((path, e.to_string()))
+		}
+	}
+
+	let manifested: HashSet<String> = manifests.iter().map(|m| m.id.clone()).collect();
+	let (ordered, rejected) = manifest::load_order(manifests);
+	failed.extend(rejected.into_iter().map(|(manifest, msg)| (manifest.library, msg)));
+	// modules depending on one without a manifest, directly or not,
+	// have to wait for those
+	let mut waiting: HashSet<String> = HashSet::new();
+	let (mut plan, mut after): (LoadPlan, LoadPlan) = (Vec::new(), Vec::new());
+	for manifest in ordered {
+		let waits = dependencies_of(Some(&manifest))
+			.iter()
+			.any(|dep| !manifested.contains(dep) || waiting.contains(dep));
+		match waits {
+			true => {
+				waiting.insert(manifest.id.clone());
+				after.push((manifest.library.clone(), Some(manifest)));
+			},
+			false => plan.push((manifest.library.clone(), Some(manifest)))
+		}
+	}
+	plan.append(&mut unmanifested);
+	plan.append(&mut after);
+	(plan, failed)
+}
+
+/// the first required dependency of the module that isn't loaded
+fn missing_dependency<'a>(mod_mgr: &modules::ModuleManager, manifest: Option<&'a ModuleManifest>) -> Option<&'a String> {
+	manifest?.dependencies.iter().find(|dep| !mod_mgr.mod_lib.contains_key(*dep))
+}
+
 pub struct ModuleCore {
 	pub mod_mgr: modules::ModuleManager,
-	pub futures: stream::FuturesUnordered<ThreadHandle>
+	pub futures: stream::FuturesUnordered<ThreadHandle>,
+	pub supervisor: supervisor::Supervisor,
+	mod_send: mpsc::UnboundedSender<(&'static str, module::ModuleCommands)>
 }
 
 impl ModuleCore {
-	pub fn new(
-		database_connection: utopia_module::UDb
+	pub async fn new(
+		database_connection: utopia_module::UDb,
+		database_url: String,
+		config: &UtopiaModulesConfig
 	) -> anyhow::Result<(
 		ModuleCore,
 		mpsc::UnboundedReceiver<(&'static str, module::ModuleCommands)>
 	)> {
-		let mut mod_mgr = modules::ModuleManager::new(database_connection);
+		let mut mod_mgr = modules::ModuleManager::new(database_connection, database_url, config.clone());
 		let (mod_send, mod_recv) = mpsc::unbounded::<(&'static str, module::ModuleCommands)>();
 		let futures = stream::FuturesUnordered::new();
-		unsafe {
-			match mod_mgr.load_module(
-				&std::ffi::OsStr::new("../utopia-dbgfiller-module/target/debug/libdbgfiller_steam_mod.so"),
-				mod_send.clone()
-			) {
-				Ok(handle) => futures.push(handle),
-				Err(e) => eprintln!("Error loading module: {}", e)
-			}
-			match mod_mgr.load_module(
-				&std::ffi::OsStr::new("../utopia-sample-module/target/debug/libsample_mod.so"),
-				mod_send.clone()
-			) {
-				Ok(handle) => futures.push(handle),
-				Err(e) => eprintln!("Error loading module: {}", e)
+		let mut supervisor = supervisor::Supervisor::new(config.clone());
+		let (plan, failed) = load_plan(find_libraries(&config.search_paths), config);
+		for (path, e) in failed {
+			eprintln!("Error loading module {}: {}", path.to_string_lossy(), e);
+		}
+		for (path, manifest) in plan {
+			if let Some(dep) = missing_dependency(&mod_mgr, manifest.as_ref()) {
+				eprintln!("Error loading module {}: missing dependency {}", path.to_string_lossy(), dep);
+				continue;
 			}
-			match mod_mgr.load_module(
-				&std::ffi::OsStr::new("../utopia-gog-dbgfiller-module/target/debug/libdbgfiller_gog_mod.so"),
-				mod_send
-			) {
-				Ok(handle) => futures.push(handle),
-				Err(e) => eprintln!("Error loading module: {}", e)
+			let dependencies = dependencies_of(manifest.as_ref());
+			match unsafe { mod_mgr.load_module(path.clone(), manifest, mod_send.clone()).await } {
+				Ok((id, handle)) => {
+					futures.push(handle);
+					supervisor.started(id, path, dependencies);
+				},
+				Err(e) if e.is::<NotAModuleError>() || e.is::<ModuleDisabledError>() => println!("Skipping: {}", e),
+				Err(e) => eprintln!("Error loading module {}: {}", path.to_string_lossy(), e)
 			}
 		}
 		Ok((
 			ModuleCore {
 				mod_mgr,
-				futures
+				futures,
+				supervisor,
+				mod_send
 			},
 			mod_recv
 		))
 	}
 
+	/// loads a single module at runtime, that was
+	/// [opened](modules::ModuleManager::open) before. Its manifest, if
+	/// any, is read again and its dependencies have to be loaded
+	/// already.
+	pub fn load(&mut self, opened: modules::OpenedModule) -> Result<&'static str, Box<dyn std::error::Error>> {
+		let path = opened.path.clone();
+		let manifest = ModuleManifest::for_library(&path)?;
+		if let Some(dep) = missing_dependency(&self.mod_mgr, manifest.as_ref()) {
+			return Err(Box::new(ManifestError::new(path, format!("missing dependency {}", dep))));
+		}
+		let dependencies = dependencies_of(manifest.as_ref());
+		let (id, handle) = self.mod_mgr.add(opened, manifest, self.mod_send.clone())?;
+		self.futures.push(handle);
+		self.supervisor.started(id, path, dependencies);
+		Ok(id)
+	}
+
+	/// loads every library in files and reports whether it was
+	/// successful. Returns the number of modules that failed to load.
+	pub async fn check(files: &[PathBuf], database_connection: utopia_module::UDb, database_url: String) -> usize {
+		// check-modules is explicitly about every module, so nothing
+		// is disabled here
+		let mut mod_mgr = modules::ModuleManager::new(database_connection, database_url, UtopiaModulesConfig::default());
+		let (mod_send, _mod_recv) = mpsc::unbounded::<(&'static str, module::ModuleCommands)>();
+		let (plan, rejected) = load_plan(files.to_vec(), &UtopiaModulesConfig::default());
+		let mut failed = rejected.len();
+		for (file, e) in rejected {
+			eprintln!("FAILED: {}: {}", file.to_string_lossy(), e);
+		}
+		for (file, manifest) in plan {
+			if let Some(dep) = missing_dependency(&mod_mgr, manifest.as_ref()) {
+				failed += 1;
+				eprintln!("FAILED: {}: missing dependency {}", file.to_string_lossy(), dep);
+				continue;
+			}
+			match unsafe { mod_mgr.load_module(file.clone(), manifest, mod_send.clone()).await } {
+				Ok((id, _handle)) => println!("OK: {} ({})", file.to_string_lossy(), id),
+				Err(e) if e.is::<NotAModuleError>() => println!("SKIPPED: {}", e),
+				Err(e) => {
+					failed += 1;
+					match e.downcast_ref::<ModuleABIError>() {
+						Some(abi) => eprintln!("ABI mismatch: {}: {}", file.to_string_lossy(), abi),
+						None => eprintln!("FAILED: {}: {}", file.to_string_lossy(), e)
+					}
+				}
+			}
+		}
+		// The module threads are still running and can't be stopped,
+		// unloading their libraries would pull the code out from
+		// under them.
+		std::mem::forget(mod_mgr);
+		failed
+	}
+
 	pub fn get_modules(&self) {
 		for (id, module) in &self.mod_mgr.modules {
 			println!("Module: {} has name: {}", id, module.module.get_module_info().name)
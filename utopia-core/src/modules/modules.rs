@@ -5,30 +5,127 @@
 
 use std::{ffi::OsStr,
           fmt::{self, Debug, Formatter},
-          sync::Arc};
+          path::{Path, PathBuf},
+          sync::{Arc, Mutex},
+          time::Duration};
 
-use futures::channel::mpsc;
+use futures::{channel::mpsc, StreamExt};
 use libloading::{Library, Symbol};
 use utopia_common::module;
-pub use utopia_module::{Module, UDb, MODULE_INTERFACE_VERSION};
+pub use utopia_module::{InterfaceVersion,
+                        Module,
+                        ModuleDescriptor,
+                        UDb,
+                        MODULE_DESCRIPTOR_SYMBOL,
+                        MODULE_INTERFACE_VERSION};
 
-use crate::errors::{self, ModuleABIError, ModuleNotAvailableError};
+use super::{manifest::ModuleManifest, remote::RemoteModule};
+use crate::{config::UtopiaModulesConfig,
+            errors::{self,
+                     ManifestError,
+                     ModuleABIError,
+                     ModuleAlreadyLoadedError,
+                     ModuleDisabledError,
+                     ModuleNotAvailableError,
+                     NotAModuleError}};
 
 pub type ThreadHandle = tokio::task::JoinHandle<(
 	&'static str,
 	Result<module::ThreadDeathExcuse, Box<dyn std::error::Error + Send + Sync>>
 )>;
 
+/// how long an unloading module gets to return from its thread
+/// function, before its library is kept loaded for good
+const UNLOAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// how a module is loaded, depending on the file it is loaded from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModuleKind {
+	/// shared library (`*.so`) that is loaded into µCore
+	Library,
+	/// executable (`*.module`) that is spawned as separate process, see
+	/// [remote](super::remote)
+	Process
+}
+impl ModuleKind {
+	/// None if the file can't be a module
+	pub fn of(path: &Path) -> Option<Self> {
+		match path.extension().and_then(OsStr::to_str) {
+			Some("so") => Some(ModuleKind::Library),
+			Some("module") => Some(ModuleKind::Process),
+			_ => None
+		}
+	}
+
+	/// whether a module of this kind, built against version, can be
+	/// loaded
+	///
+	/// libraries share µCore's types, so they have to match exactly.
+	pub fn accepts(self, version: InterfaceVersion) -> bool {
+		match self {
+			ModuleKind::Library => version == MODULE_INTERFACE_VERSION,
+			ModuleKind::Process => MODULE_INTERFACE_VERSION.accepts(version)
+		}
+	}
+
+	/// the versions [accepts](Self::accepts) lets through
+	pub fn accepted_versions(self) -> String {
+		match self {
+			ModuleKind::Library => format!("only {}", MODULE_INTERFACE_VERSION),
+			ModuleKind::Process => format!("{}.0 up to {}", MODULE_INTERFACE_VERSION.major, MODULE_INTERFACE_VERSION)
+		}
+	}
+}
+
+/// a module created from its file, that isn't added to the
+/// [ModuleManager] yet
+pub struct OpenedModule {
+	pub path: PathBuf,
+	/// declared before the library, so it's dropped first. Otherwise
+	/// the vtable would point to garbage.
+	module: Box<dyn Module>,
+	library: Option<Library>
+}
+impl Debug for OpenedModule {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		f.debug_struct("OpenedModule").field("path", &self.path).finish()
+	}
+}
+
+/// returns a copy of id, that lives for the rest of the program
+///
+/// the &'static str a module returns as its id points into its
+/// library, so it must not outlive the module being unloaded.
+pub fn intern(id: &str) -> &'static str {
+	static IDS: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+	let mut ids = IDS.lock().unwrap();
+	match ids.iter().find(|known| **known == id) {
+		Some(known) => known,
+		None => {
+			let leaked: &'static str = Box::leak(id.to_owned().into_boxed_str());
+			ids.push(leaked);
+			leaked
+		}
+	}
+}
+
 pub struct IModule {
 	pub module: Arc<std::boxed::Box<dyn Module>>,
 	pub send: mpsc::UnboundedSender<module::CoreCommands>
 }
 impl IModule {
+	/// spawns the thread of module. Messages from the module are
+	/// forwarded to mod_send, tagged with the id the module was loaded
+	/// as.
 	pub fn new(
+		id: &'static str,
 		module: Box<dyn Module>,
 		mod_send: mpsc::UnboundedSender<(&'static str, module::ModuleCommands)>
 	) -> (Self, ThreadHandle) {
 		let (core_send, core_recv) = mpsc::unbounded::<module::CoreCommands>();
+		let (module_send, module_recv) = mpsc::unbounded::<(&'static str, module::ModuleCommands)>();
+		tokio::spawn(module_recv.map(move |(_, cmd)| Ok((id, cmd))).forward(mod_send));
+
 		let module = IModule {
 			module: Arc::new(module),
 			send: core_send
@@ -36,68 +133,218 @@ impl IModule {
 		let module_ptr = module.module.clone();
 		(
 			module,
-			tokio::spawn(async move { module_ptr.thread(mod_send, core_recv) })
+			// the thread function blocks for the lifetime of the module,
+			// so it must not occupy one of the runtime's workers
+			tokio::task::spawn_blocking(move || {
+				// Neither the id nor the error must reference the module's
+				// library, as it may be unloaded before this is handled.
+				let thread = std::panic::AssertUnwindSafe(|| module_ptr.thread(module_send, core_recv));
+				match std::panic::catch_unwind(thread) {
+					Ok((_, res)) => (id, res.map_err(|e| e.to_string().into())),
+					Err(_) => (id, Err("the module panicked".into()))
+				}
+			})
 		)
 	}
 
-	pub fn send(&self, cmd: module::CoreCommands) -> Result<(), mpsc::TrySendError<module::CoreCommands>> {
-		self.send.unbounded_send(cmd)
+	/// fails if the module's thread returned already
+	pub fn send(&self, cmd: module::CoreCommands) -> Result<(), mpsc::SendError> {
+		self.send.unbounded_send(cmd).map_err(|e| e.into_send_error())
+	}
+}
+
+/// a module whose thread was told to stop, along with its library
+pub struct UnloadingModule {
+	pub id: &'static str,
+	/// library or executable the module was loaded from
+	pub path: PathBuf,
+	module: Arc<Box<dyn Module>>,
+	library: Option<Library>
+}
+impl UnloadingModule {
+	/// waits for the module thread to return and closes the library.
+	///
+	/// If the thread doesn't return in time the library is kept
+	/// loaded forever, as the thread is still executing its code.
+	pub async fn finish(self) -> Result<(), errors::ModuleUnloadError> {
+		let deadline = tokio::time::Instant::now() + UNLOAD_TIMEOUT;
+		// the thread task holds the only other reference
+		while Arc::strong_count(&self.module) > 1 {
+			if tokio::time::Instant::now() >= deadline {
+				let id = self.id;
+				std::mem::forget(self);
+				return Err(errors::ModuleUnloadError::new(id, "thread did not stop in time, keeping it loaded"));
+			}
+			tokio::time::sleep(Duration::from_millis(50)).await;
+		}
+		// the module has to be gone before its code is unloaded
+		drop(self.module);
+		drop(self.library);
+		Ok(())
 	}
 }
 
 pub struct ModuleManager {
 	pub modules: std::collections::HashMap<&'static str, IModule>,
 	pub mod_lib: std::collections::HashMap<String, &'static str>,
-	loaded_libraries: Vec<Library>,
-	connection: UDb
+	/// manifests of the loaded modules that have one
+	pub manifests: std::collections::HashMap<&'static str, ModuleManifest>,
+	/// paths of the loaded modules, with their library unless they run
+	/// out of process
+	loaded_libraries: std::collections::HashMap<&'static str, (PathBuf, Option<Library>)>,
+	connection: UDb,
+	/// passed to out-of-process modules, which connect on their own
+	database_url: String,
+	selection: UtopiaModulesConfig
 }
 
 impl ModuleManager {
-	pub fn new(connection: UDb) -> ModuleManager {
+	pub fn new(connection: UDb, database_url: String, selection: UtopiaModulesConfig) -> ModuleManager {
 		ModuleManager {
 			modules: std::collections::HashMap::new(),
 			mod_lib: std::collections::HashMap::new(),
-			loaded_libraries: Vec::new(),
-			connection
+			manifests: std::collections::HashMap::new(),
+			loaded_libraries: std::collections::HashMap::new(),
+			connection,
+			database_url,
+			selection
 		}
 	}
 
-	pub unsafe fn load_module<P: AsRef<OsStr>>(
+	pub async unsafe fn load_module(
 		&mut self,
-		filename: P,
+		path: PathBuf,
+		manifest: Option<ModuleManifest>,
 		mod_send: mpsc::UnboundedSender<(&'static str, module::ModuleCommands)>
-	) -> Result<ThreadHandle, Box<dyn std::error::Error>> {
-		type ModuleCreate = unsafe fn() -> *mut dyn Module;
-
-		let lib = Library::new(filename.as_ref())?; /* (|| "Unable to load the plugin")?; */
+	) -> Result<(&'static str, ThreadHandle), Box<dyn std::error::Error>> {
+		let opened = self.open(path).await.map_err(|e| e as Box<dyn std::error::Error>)?;
+		self.add(opened, manifest, mod_send)
+	}
 
-		// We need to keep the library around otherwise our plugin's vtable
-		// will point to garbage. We do this little dance to make sure the
-		// library doesn't end up getting moved.
-		self.loaded_libraries.push(lib);
+	/// loads the library at path, or spawns the module executable and
+	/// waits for it to introduce itself
+	///
+	/// the returned future doesn't borrow the manager, so it can run
+	/// in the background.
+	pub unsafe fn open(
+		&self,
+		path: PathBuf
+	) -> impl std::future::Future<Output = Result<OpenedModule, Box<dyn std::error::Error + Send + Sync>>> + Send + 'static {
+		let database_url = self.database_url.clone();
+		async move {
+			match ModuleKind::of(&path) {
+				Some(ModuleKind::Library) => {
+					let library = Library::new(&path)?;
+					let module = Self::create_module(&library, &path)?;
+					Ok(OpenedModule {
+						path,
+						module,
+						library: Some(library)
+					})
+				},
+				// checks the version the process reports
+				Some(ModuleKind::Process) => Ok(OpenedModule {
+					module: Box::new(RemoteModule::spawn(path.clone(), database_url).await?),
+					path,
+					library: None
+				}),
+				None => Err(Box::new(NotAModuleError::new(path)) as Box<dyn std::error::Error + Send + Sync>)
+			}
+		}
+	}
 
-		let lib = self.loaded_libraries.last().unwrap();
+	/// initialises an opened module and spawns its thread
+	pub fn add(
+		&mut self,
+		mut opened: OpenedModule,
+		manifest: Option<ModuleManifest>,
+		mod_send: mpsc::UnboundedSender<(&'static str, module::ModuleCommands)>
+	) -> Result<(&'static str, ThreadHandle), Box<dyn std::error::Error>> {
+		let path = opened.path.as_path();
+		let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+		let file_stem = path.file_stem().unwrap_or_default().to_string_lossy();
+		let id = intern(opened.module.id());
+		if self.modules.contains_key(id) {
+			return Err(Box::new(ModuleAlreadyLoadedError::new(id)));
+		}
+		if !self.selection.is_enabled(&[id, &file_name, &file_stem]) {
+			return Err(Box::new(ModuleDisabledError::new(id)));
+		}
+		if let Some(manifest) = manifest.as_ref().filter(|manifest| manifest.id != id) {
+			let msg = format!("declares the id {}, but the module reports {}", manifest.id, id);
+			return Err(Box::new(ManifestError::new(manifest.library.clone(), msg)));
+		}
 
-		let constructor: Symbol<ModuleCreate> = lib.get(b"_module_create")?;
-		/* .chain_err(|| "The `_module_create` symbol wasn't found.")?; */
-		let boxed_raw = constructor();
+		println!("Loaded module: {}", opened.module.get_module_info().name);
+		opened.module.init(self.connection.clone());
 
-		let mut module = Box::from_raw(boxed_raw);
-		if module.__abi_version() == MODULE_INTERFACE_VERSION {
-			println!("Loaded module: {}", module.get_module_info().name);
-			module.init(self.connection.clone());
+		let OpenedModule {
+			path,
+			module,
+			library
+		} = opened;
+		let (module, resolve) = IModule::new(id, module, mod_send);
+		self.mod_lib.insert(id.to_string(), id);
+		self.modules.insert(id, module);
+		self.loaded_libraries.insert(id, (path, library));
+		if let Some(manifest) = manifest {
+			self.manifests.insert(id, manifest);
+		}
+		Ok((id, resolve))
+	}
 
-			let (module, resolve) = IModule::new(module, mod_send);
-			self.mod_lib.insert(module.module.id().to_string(), module.module.id());
-			self.modules.insert(module.module.id(), module);
-			Ok(resolve)
-		} else {
-			Err(Box::new(ModuleABIError::new(
-				module.id(),
-				module.__abi_version(),
-				MODULE_INTERFACE_VERSION
-			)))
+	/// creates the module exported by lib through its
+	/// [ModuleDescriptor]
+	///
+	/// the interface version is checked before anything else of the
+	/// module is touched.
+	unsafe fn create_module(
+		lib: &Library,
+		path: &Path
+	) -> Result<Box<dyn Module>, Box<dyn std::error::Error + Send + Sync>> {
+		let descriptor: Symbol<*const ModuleDescriptor> = match lib.get(MODULE_DESCRIPTOR_SYMBOL) {
+			Ok(descriptor) => descriptor,
+			// modules built before the interface was versioned export
+			// a Rust trait object instead
+			Err(_) if lib.get::<*const ()>(b"_module_create").is_ok() => {
+				return Err(Box::new(ModuleABIError::new(path.to_owned(), None)));
+			},
+			Err(_) => return Err(Box::new(NotAModuleError::new(path.to_owned())))
+		};
+		let descriptor: &ModuleDescriptor = &**descriptor;
+		if !ModuleKind::Library.accepts(descriptor.version) {
+			return Err(Box::new(ModuleABIError::new(path.to_owned(), Some(descriptor.version))));
 		}
+		let boxed = (descriptor.create)() as *mut Box<dyn Module>;
+		Ok(*Box::from_raw(boxed))
+	}
+
+	/// Stops a single module and removes it from the manager.
+	///
+	/// The module's deinit function is called and its command channel
+	/// closed, which should make its thread return. Awaiting
+	/// [finish](UnloadingModule::finish) on the result closes the
+	/// library once that happened.
+	pub fn unload(&mut self, id: &str) -> Result<UnloadingModule, errors::ProvModuleNotAvailableError> {
+		let id = self
+			.mod_lib
+			.remove(id)
+			.ok_or_else(|| errors::ProvModuleNotAvailableError::new(id.to_string()))?;
+		println!("Unloading {}", id);
+		self.manifests.remove(id);
+		let IModule {
+			module,
+			send
+		} = self.modules.remove(id).unwrap();
+		module.deinit();
+		drop(send);
+		let (path, library) = self.loaded_libraries.remove(id).unwrap();
+		Ok(UnloadingModule {
+			id,
+			path,
+			module,
+			library
+		})
 	}
 
 	/// Unload all plugins and loaded plugin libraries, making sure to
@@ -106,16 +353,23 @@ impl ModuleManager {
 	pub fn deinit(&mut self) {
 		println!("Unloading plugins");
 
+		self.manifests.clear();
+		self.mod_lib.clear();
 		for (id, module) in self.modules.drain() {
 			println!("Unloading {}", id);
 			module.module.deinit();
 		}
 
-		for lib in self.loaded_libraries.drain(..) {
+		for (_id, (_path, lib)) in self.loaded_libraries.drain() {
 			drop(lib);
 		}
 	}
 
+	/// path of the library module was loaded from
+	pub fn library_path(&self, id: &str) -> Option<&PathBuf> {
+		self.loaded_libraries.get(id).map(|(path, _)| path)
+	}
+
 	pub fn get(&self, uuid: &'static str) -> Result<&IModule, ModuleNotAvailableError> {
 		match self.modules.get(uuid) {
 			Some(module) => Ok(module),
@@ -143,8 +397,8 @@ impl Debug for ModuleManager {
 	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
 		let modules: Vec<_> = self
 			.modules
-			.iter()
-			.map(|(_id, module)| module.module.get_module_info().name)
+			.values()
+			.map(|module| module.module.get_module_info().name)
 			.collect();
 
 		f.debug_struct("ModuleManager").field("modules", &modules).finish()
@@ -0,0 +1,194 @@
+//! Out-of-process modules
+//!
+//! Executables named `*.module` in the module directories are spawned
+//! as separate process and talk to µCore using the
+//! [ipc](utopia_common::ipc) protocol. [RemoteModule] wraps such a
+//! process into a [Module], so the
+//! [ModuleManager](super::modules::ModuleManager) treats it just like
+//! a module loaded from a shared library.
+use std::{path::PathBuf, process::Stdio, sync::Mutex, time::Duration};
+
+use futures::StreamExt;
+use tokio::{io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+            process::{Child, ChildStdin, ChildStdout, Command}};
+use utopia_common::{ipc::{CoreMessage, ModuleMessage},
+                    module};
+use utopia_module::{InterfaceVersion, Module, UDb, URecv, URes, USend};
+
+use super::modules::{intern, ModuleKind};
+use crate::errors::{FileError, ModuleABIError, ModuleProcessError};
+
+/// how long a module process gets to introduce itself
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+/// how long a module process gets to exit after it was asked to
+/// stop, before it is killed
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn encode(msg: &CoreMessage) -> serde_json::Result<Vec<u8>> {
+	let mut line = serde_json::to_vec(msg)?;
+	line.push(b'\n');
+	Ok(line)
+}
+
+struct Process {
+	/// killed when dropped, e.g. if the module is never started
+	child: Child,
+	stdin: ChildStdin,
+	stdout: Lines<BufReader<ChildStdout>>,
+	/// sent before any command, once the module was initialised
+	init: Option<Vec<u8>>
+}
+impl Process {
+	/// relays messages between the module process and µCore until the
+	/// process exits
+	async fn run(
+		self,
+		id: &'static str,
+		mod_send: USend,
+		mut core_recv: URecv
+	) -> Result<module::ThreadDeathExcuse, Box<dyn std::error::Error + Send + Sync>> {
+		let Process {
+			mut child,
+			stdin,
+			stdout: mut lines,
+			init
+		} = self;
+		let mut stdin = Some(stdin);
+		if let Some(init) = init {
+			// a process that exited already is noticed by its stdout
+			// closing
+			let _ = stdin.as_mut().unwrap().write_all(&init).await;
+		}
+		let mut result = None;
+		let mut deadline: Option<tokio::time::Instant> = None;
+		loop {
+			tokio::select! {
+				line = lines.next_line() => match line {
+					Ok(Some(line)) => match serde_json::from_str::<ModuleMessage>(&line) {
+						Ok(ModuleMessage::Command(cmd)) => {
+							let _ = mod_send.unbounded_send((id, cmd));
+						},
+						Ok(ModuleMessage::Died(excuse)) => result = Some(Ok(excuse)),
+						Ok(ModuleMessage::Error(e)) => result = Some(Err(e.into())),
+						Ok(ModuleMessage::Hello { .. }) => eprintln!("Module {} introduced itself again", id),
+						Err(e) => eprintln!("Invalid message from module {}: {}", id, e)
+					},
+					Ok(None) => break,
+					Err(e) => {
+						eprintln!("Lost connection to module {}: {}", id, e);
+						break;
+					}
+				},
+				cmd = core_recv.next(), if stdin.is_some() => match cmd {
+					Some(cmd) => {
+						let line = encode(&CoreMessage::Command(cmd))?;
+						// a process that exited is noticed by its stdout
+						// closing
+						let _ = stdin.as_mut().unwrap().write_all(&line).await;
+					},
+					None => {
+						// closing stdin asks the module to stop
+						stdin = None;
+						deadline = Some(tokio::time::Instant::now() + STOP_TIMEOUT);
+					}
+				},
+				_ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)), if deadline.is_some() => {
+					eprintln!("Module {} didn't stop in time, killing it", id);
+					let _ = child.start_kill();
+					deadline = None;
+				}
+			}
+		}
+		let status = child.wait().await?;
+		match result {
+			Some(result) => result,
+			None => Err(format!("the module process exited unexpectedly ({})", status).into())
+		}
+	}
+}
+
+/// a module running in a separate process
+pub struct RemoteModule {
+	id: &'static str,
+	info: module::ModuleInfo,
+	database_url: String,
+	/// taken by the module thread
+	process: Mutex<Option<Process>>
+}
+impl RemoteModule {
+	/// spawns the module executable at path and waits for it to
+	/// introduce itself
+	pub async fn spawn(path: PathBuf, database_url: String) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+		// on every error below the process is killed by dropping it
+		let mut child = Command::new(&path)
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.kill_on_drop(true)
+			.spawn()
+			.map_err(|io| FileError::new(path.clone(), io))?;
+		let stdin = child.stdin.take().unwrap();
+		let mut stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+		let line = match tokio::time::timeout(HELLO_TIMEOUT, stdout.next_line()).await {
+			Ok(Ok(Some(line))) => line,
+			Ok(Ok(None)) => return Err(ModuleProcessError::new(path, "no hello received: the process exited").into()),
+			Ok(Err(e)) => return Err(ModuleProcessError::new(path, format!("no hello received: {}", e)).into()),
+			Err(_) => return Err(ModuleProcessError::new(path, "no hello received: timed out").into())
+		};
+		let (id, abi_version, info) = match serde_json::from_str::<ModuleMessage>(&line) {
+			Ok(ModuleMessage::Hello {
+				id,
+				abi_version,
+				info
+			}) => (id, abi_version, info),
+			Ok(msg) => return Err(ModuleProcessError::new(path, format!("expected hello, got {:?}", msg)).into()),
+			Err(e) => return Err(ModuleProcessError::new(path, format!("invalid hello: {}", e)).into())
+		};
+		// the protocol carries the same commands as the in-process
+		// interface, so the same versioning applies
+		match abi_version.parse::<InterfaceVersion>() {
+			Ok(version) if ModuleKind::Process.accepts(version) => (),
+			res => return Err(ModuleABIError::new(path, res.ok()).into())
+		}
+		Ok(RemoteModule {
+			id: intern(&id),
+			info,
+			database_url,
+			process: Mutex::new(Some(Process {
+				child,
+				stdin,
+				stdout,
+				init: None
+			}))
+		})
+	}
+}
+
+impl Module for RemoteModule {
+	fn id(&self) -> &'static str {
+		self.id
+	}
+
+	fn get_module_info(&self) -> module::ModuleInfo {
+		self.info.clone()
+	}
+
+	/// the module connects to the database on its own
+	fn init(&mut self, _db: UDb) {
+		if let Some(process) = self.process.get_mut().unwrap() {
+			let init = CoreMessage::Init {
+				database: self.database_url.clone()
+			};
+			process.init = encode(&init).ok();
+		}
+	}
+
+	fn thread(&self, mod_send: USend, core_recv: URecv) -> URes {
+		let process = self.process.lock().unwrap().take();
+		let res = match process {
+			// the thread runs on a blocking thread of µCore's runtime
+			Some(process) => tokio::runtime::Handle::current().block_on(process.run(self.id, mod_send, core_recv)),
+			None => Err("the module process was started already".into())
+		};
+		(self.id, res)
+	}
+}
@@ -0,0 +1,149 @@
+use std::{collections::HashMap,
+          path::PathBuf,
+          time::{Duration, Instant}};
+
+use utopia_common::module::ThreadDeathExcuse;
+
+use crate::config::{RestartStrategy, UtopiaModulesConfig};
+
+/// what to do with a module whose thread returned
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decision {
+	Restart(Duration),
+	/// restart once one of its dependencies is loaded again
+	WaitForDependency,
+	/// the module failed too often in a row
+	GiveUp(u32),
+	/// the module's policy doesn't restart it
+	Never
+}
+
+struct Supervised {
+	path: PathBuf,
+	/// ids of the modules it depends on, empty if unknown
+	dependencies: Vec<String>,
+	/// when the module was last loaded, until it died. Failing to load
+	/// it again doesn't count as running.
+	started: Option<Instant>,
+	failures: u32
+}
+
+/// keeps track of module restarts according to their
+/// [SupervisionPolicy](crate::config::SupervisionPolicy)
+pub struct Supervisor {
+	config: UtopiaModulesConfig,
+	modules: HashMap<&'static str, Supervised>,
+	waiting: Vec<&'static str>
+}
+impl Supervisor {
+	pub fn new(config: UtopiaModulesConfig) -> Self {
+		Supervisor {
+			config,
+			modules: HashMap::new(),
+			waiting: Vec::new()
+		}
+	}
+
+	/// a module was loaded from path
+	pub fn started(&mut self, id: &'static str, path: PathBuf, dependencies: Vec<String>) {
+		self.waiting.retain(|waiting| *waiting != id);
+		let failures = self.modules.get(id).map(|s| s.failures).unwrap_or(0);
+		self.modules.insert(id, Supervised {
+			path,
+			dependencies,
+			started: Some(Instant::now()),
+			failures
+		});
+	}
+
+	/// the module was unloaded on purpose, it's no longer supervised
+	pub fn forget(&mut self, id: &str) {
+		self.modules.remove(id);
+		self.waiting.retain(|waiting| *waiting != id);
+	}
+
+	/// decides what happens to a module whose thread returned or that
+	/// failed to start again
+	pub fn died(&mut self, id: &'static str, excuse: Option<&ThreadDeathExcuse>) -> Decision {
+		let policy = self.config.policy(id).clone();
+		let supervised = match self.modules.get_mut(id) {
+			Some(supervised) => supervised,
+			None => return Decision::Never
+		};
+		if policy.restart == RestartStrategy::Never {
+			return Decision::Never;
+		}
+		if let Some(ThreadDeathExcuse::HiracyDeath) = excuse {
+			self.waiting.push(id);
+			return Decision::WaitForDependency;
+		}
+		// a module that ran for a while recovered from earlier failures
+		let ran = supervised.started.take().map(|started| started.elapsed());
+		if ran.is_some_and(|ran| ran >= Duration::from_secs(policy.max_delay)) {
+			supervised.failures = 0;
+		}
+		supervised.failures += 1;
+		if policy.max_failures != 0 && supervised.failures > policy.max_failures {
+			return Decision::GiveUp(supervised.failures);
+		}
+		Decision::Restart(policy.delay(supervised.failures))
+	}
+
+	/// a module came back, returns the modules waiting for it
+	pub fn dependency_returned(&mut self, id: &str) -> Vec<(&'static str, PathBuf)> {
+		let modules = &self.modules;
+		let (ready, waiting): (Vec<&'static str>, Vec<&'static str>) =
+			self.waiting.iter().partition(|waiting| match modules.get(**waiting) {
+				Some(s) => s.dependencies.is_empty() || s.dependencies.iter().any(|dep| dep == id),
+				None => false
+			});
+		self.waiting = waiting;
+		ready
+			.into_iter()
+			.filter_map(|id| self.modules.get(id).map(|s| (id, s.path.clone())))
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::config::SupervisionPolicy;
+
+	fn supervisor() -> Supervisor {
+		Supervisor::new(UtopiaModulesConfig {
+			supervision: SupervisionPolicy {
+				restart: RestartStrategy::Backoff,
+				max_failures: 3,
+				initial_delay: 1,
+				max_delay: 2
+			},
+			..Default::default()
+		})
+	}
+
+	#[test]
+	fn failed_loads_after_a_long_run_give_up() {
+		let mut supervisor = supervisor();
+		supervisor.started("test", PathBuf::from("libtest.so"), Vec::new());
+		let started = Instant::now().checked_sub(Duration::from_secs(2)).unwrap();
+		supervisor.modules.get_mut("test").unwrap().started = Some(started);
+
+		// the long run resets the count, the failed loads don't
+		assert_eq!(supervisor.died("test", None), Decision::Restart(Duration::from_secs(1)));
+		assert_eq!(supervisor.died("test", None), Decision::Restart(Duration::from_secs(2)));
+		assert_eq!(supervisor.died("test", None), Decision::Restart(Duration::from_secs(2)));
+		assert_eq!(supervisor.died("test", None), Decision::GiveUp(4));
+	}
+
+	#[test]
+	fn short_runs_count_as_failures() {
+		let mut supervisor = supervisor();
+		for failures in 1..=3 {
+			supervisor.started("test", PathBuf::from("libtest.so"), Vec::new());
+			assert!(matches!(supervisor.died("test", None), Decision::Restart(_)), "failure {}", failures);
+		}
+		supervisor.started("test", PathBuf::from("libtest.so"), Vec::new());
+		assert_eq!(supervisor.died("test", None), Decision::GiveUp(4));
+	}
+}
@@ -0,0 +1,142 @@
+//! Watching processes of library items until they exit
+//!
+//! Games are usually spawned by modules, so they aren't children of
+//! µCore and waitpid can't wait for them. A pidfd becomes readable once
+//! its process exits, no matter whose child it is. On kernels without
+//! pidfd_open (before 5.3) /proc is polled instead. Exit codes are only
+//! known for children of µCore, which are reaped as well.
+pub mod tree;
+
+use std::{fmt::{self, Display, Formatter},
+          fs,
+          io,
+          os::unix::io::{FromRawFd, OwnedFd, RawFd},
+          time::Duration};
+
+use tokio::io::unix::AsyncFd;
+use utopia_common::library::CrashInfo;
+
+/// how often /proc is checked without pidfd support
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// how a process ended, as far as it's known
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Exit {
+	pub code: Option<i32>,
+	/// signal that terminated the process
+	pub signal: Option<i32>
+}
+impl Exit {
+	fn from_wait_status(status: libc::c_int) -> Self {
+		match libc::WIFSIGNALED(status) {
+			true => Exit {
+				code: None,
+				signal: Some(libc::WTERMSIG(status))
+			},
+			false => Exit {
+				code: Some(libc::WEXITSTATUS(status)),
+				signal: None
+			}
+		}
+	}
+
+	/// None if the process exited successfully or its status is
+	/// unknown
+	pub fn crash_info(&self) -> Option<CrashInfo> {
+		match (self.code, self.signal) {
+			(_, Some(signal)) => Some(CrashInfo {
+				signal: Some(signal),
+				..Default::default()
+			}),
+			(Some(code), None) if code != 0 => Some(CrashInfo {
+				exit_code: Some(code),
+				..Default::default()
+			}),
+			_ => None
+		}
+	}
+}
+impl Display for Exit {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		match (self.code, self.signal) {
+			(_, Some(signal)) => write!(f, "signal {}", signal),
+			(Some(code), None) => write!(f, "exit code {}", code),
+			(None, None) => write!(f, "an unknown status")
+		}
+	}
+}
+
+/// resolves once process pid exited, without blocking the runtime
+pub async fn wait(pid: u32) -> Exit {
+	match pidfd_open(pid).and_then(AsyncFd::new) {
+		Ok(fd) => match fd.readable().await {
+			Ok(_) => reap(pid).unwrap_or_default(),
+			Err(e) => {
+				eprintln!("Failed watching process {} with a pidfd, polling instead: {}", pid, e);
+				poll_proc(pid).await
+			}
+		},
+		// already gone, maybe reaped by the module that started it
+		Err(e) if e.raw_os_error() == Some(libc::ESRCH) => reap(pid).unwrap_or_default(),
+		Err(_) => poll_proc(pid).await
+	}
+}
+
+fn pidfd_open(pid: u32) -> io::Result<OwnedFd> {
+	match unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) } {
+		-1 => Err(io::Error::last_os_error()),
+		fd => Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+	}
+}
+
+/// collects the status of pid if it is an exited child of µCore
+fn reap(pid: u32) -> Option<Exit> {
+	let mut status = 0;
+	match unsafe { libc::waitpid(pid as libc::pid_t, &mut status, libc::WNOHANG) } {
+		ret if ret == pid as libc::pid_t => Some(Exit::from_wait_status(status)),
+		_ => None
+	}
+}
+
+/// the parts of /proc/<pid>/stat µCore cares about
+struct Stat {
+	state: char,
+	parent: u32,
+	group: u32,
+	/// clock ticks after boot
+	start: u64
+}
+
+/// None if pid doesn't exist
+fn proc_stat(pid: u32) -> Option<Stat> {
+	let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+	// the command name in parentheses may contain spaces itself
+	let mut fields = stat.get(stat.rfind(')')? + 1..)?.split_whitespace();
+	let state = fields.next()?.chars().next()?;
+	let parent = fields.next()?.parse().ok()?;
+	let group = fields.next()?.parse().ok()?;
+	let start = fields.nth(16)?.parse().ok()?;
+	Some(Stat {
+		state,
+		parent,
+		group,
+		start
+	})
+}
+
+/// checks /proc until pid is gone, a zombie or reused by another
+/// process
+async fn poll_proc(pid: u32) -> Exit {
+	let started = proc_stat(pid).map(|stat| stat.start);
+	let mut interval = tokio::time::interval(POLL_INTERVAL);
+	loop {
+		interval.tick().await;
+		if let Some(exit) = reap(pid) {
+			return exit;
+		}
+		match proc_stat(pid) {
+			Some(stat) if stat.state != 'Z' && Some(stat.start) == started => continue,
+			_ => return Exit::default()
+		}
+	}
+}
@@ -0,0 +1,158 @@
+//! Every process a launched item consists of
+//!
+//! Games started through wrapper scripts, Wine or Proton leave the pid
+//! a module reported behind and keep running in processes of their
+//! own. The reported process is moved into a transient cgroup, which
+//! the processes it spawns from then on inherit. Where µCore can't
+//! create cgroups (no cgroup v2, or the hierarchy isn't delegated to
+//! it), a process group the module started the item in is used
+//! instead.
+use std::{fs,
+          io,
+          path::{Path, PathBuf}};
+
+use super::{proc_stat, POLL_INTERVAL};
+
+#[derive(Debug, Clone)]
+pub enum Tree {
+	/// directory of a transient cgroup
+	Cgroup(PathBuf),
+	/// process group led by the pid
+	Group(u32),
+	/// only the pid itself is known
+	Single(u32)
+}
+impl Tree {
+	/// groups pid with the processes it spawns from now on, and the
+	/// ones it spawned already as far as they can still be found
+	///
+	/// blocks while searching /proc for them.
+	pub fn adopt(pid: u32) -> Self {
+		match create_cgroup(pid) {
+			Ok(dir) => Tree::Cgroup(dir),
+			Err(_) if unsafe { libc::getpgid(pid as libc::pid_t) } == pid as libc::pid_t => Tree::Group(pid),
+			Err(_) => Tree::Single(pid)
+		}
+	}
+
+	/// whether any process other than the reported one is left
+	pub fn alive(&self) -> bool {
+		match self {
+			Tree::Cgroup(dir) => fs::read_to_string(dir.join("cgroup.events"))
+				.map(|events| events.lines().any(|line| line == "populated 1"))
+				.unwrap_or(false),
+			Tree::Group(pgid) => match unsafe { libc::kill(-(*pgid as libc::pid_t), 0) } {
+				0 => true,
+				_ => io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+			},
+			Tree::Single(_) => false
+		}
+	}
+
+	pub fn signal(&self, signal: libc::c_int) -> io::Result<()> {
+		match self {
+			Tree::Cgroup(dir) => {
+				// cgroup.kill exists since Linux 5.14
+				if signal == libc::SIGKILL && fs::write(dir.join("cgroup.kill"), "1").is_ok() {
+					return Ok(());
+				}
+				// processes exiting meanwhile don't keep the others from
+				// getting the signal
+				let mut res = Ok(());
+				for pid in fs::read_to_string(dir.join("cgroup.procs"))?.lines().filter_map(|pid| pid.parse().ok()) {
+					match kill(pid, signal) {
+						Err(e) if e.raw_os_error() != Some(libc::ESRCH) && res.is_ok() => res = Err(e),
+						_ => ()
+					}
+				}
+				res
+			},
+			Tree::Group(pgid) => kill(-(*pgid as libc::pid_t), signal),
+			Tree::Single(pid) => kill(*pid as libc::pid_t, signal)
+		}
+	}
+
+	/// resolves once every process but the reported one exited, see
+	/// [wait](super::wait) for that one
+	pub async fn wait(&self) {
+		let mut interval = tokio::time::interval(POLL_INTERVAL);
+		while self.alive() {
+			interval.tick().await;
+		}
+	}
+
+	/// removes the cgroup, which only works once it is empty
+	pub fn release(&self) {
+		if let Tree::Cgroup(dir) = self {
+			if let Err(e) = fs::remove_dir(dir) {
+				eprintln!("Failed removing cgroup {}: {}", dir.to_string_lossy(), e);
+			}
+		}
+	}
+}
+
+fn kill(pid: libc::pid_t, signal: libc::c_int) -> io::Result<()> {
+	match unsafe { libc::kill(pid, signal) } {
+		0 => Ok(()),
+		_ => Err(io::Error::last_os_error())
+	}
+}
+
+/// directory of the cgroup µCore runs in
+fn own_cgroup() -> io::Result<PathBuf> {
+	let not_found = |what: &str| io::Error::new(io::ErrorKind::NotFound, format!("no {}", what));
+	let mounts = fs::read_to_string("/proc/self/mounts")?;
+	let root = mounts
+		.lines()
+		.map(|line| line.split(' ').collect::<Vec<_>>())
+		.find(|fields| fields.get(2) == Some(&"cgroup2"))
+		.and_then(|fields| fields.get(1).map(PathBuf::from))
+		.ok_or_else(|| not_found("cgroup2 mount"))?;
+	let cgroups = fs::read_to_string("/proc/self/cgroup")?;
+	let own = cgroups
+		.lines()
+		.find_map(|line| line.strip_prefix("0::"))
+		.ok_or_else(|| not_found("cgroup v2 membership"))?;
+	Ok(root.join(Path::new(own.trim_start_matches('/'))))
+}
+
+/// pid and the processes descending from it or in the process group
+/// it leads. A process whose parent exited already is only found
+/// through the group.
+fn members(pid: u32) -> Vec<u32> {
+	let stats: Vec<(u32, u32, u32)> = match fs::read_dir("/proc") {
+		Ok(entries) => entries
+			.filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+			.filter_map(|other| proc_stat(other).map(|stat| (other, stat.parent, stat.group)))
+			.collect(),
+		Err(_) => Vec::new()
+	};
+	let mut members = vec![pid];
+	let mut i = 0;
+	while i < members.len() {
+		let parent = members[i];
+		let found: Vec<u32> = stats
+			.iter()
+			.filter(|(other, ppid, group)| (*ppid == parent || *group == pid) && !members.contains(other))
+			.map(|(other, _, _)| *other)
+			.collect();
+		members.extend(found);
+		i += 1;
+	}
+	members
+}
+
+/// creates a cgroup below µCore's own one and moves pid into it
+fn create_cgroup(pid: u32) -> io::Result<PathBuf> {
+	let dir = own_cgroup()?.join(format!("utopia-item-{}", pid));
+	fs::create_dir(&dir)?;
+	if let Err(e) = fs::write(dir.join("cgroup.procs"), pid.to_string()) {
+		let _ = fs::remove_dir(&dir);
+		return Err(e);
+	}
+	for member in members(pid).into_iter().skip(1) {
+		// it may have exited in the meantime
+		let _ = fs::write(dir.join("cgroup.procs"), member.to_string());
+	}
+	Ok(dir)
+}
@@ -24,7 +24,7 @@
 //! ### Example of adding a new library item
 //! this shows how you can, with the help of [sender](crate::USend),
 //! add a new library item to the core.
-//! ```rust
+//! ```rust,ignore
 //! use utopia_module::{module::LibraryItem, USend};
 //!
 //! // define a new library item
@@ -40,7 +40,7 @@
 //! The following example describes how to implement a very simple
 //! module that does nothing:
 //!
-//! ```rust
+//! ```rust,ignore
 //! use anyhow::bail;
 //! use utopia_module::{Module, module, spawn_async_runtime, declare_module};
 //! use utopia_module::{USend, URecv, URes};
@@ -58,7 +58,8 @@
 //! 			developer: String::from("sp1rit"),
 //! 			developer_url: Some(String::from("https://sp1rit.ml")),
 //! 			description: None,
-//! 			icon: None
+//! 			icon: None,
+//! 			closes_items: false
 //! 		}
 //! 	}
 //!
@@ -77,6 +78,22 @@
 //!
 //! declare_module!(SampleMod, SampleMod::default);
 //! ```
+//!
+//! ## Out-of-process modules
+//! Modules loaded as shared library run inside of µCore, so a crash
+//! takes down the whole daemon. A module can instead be built as an
+//! executable that µCore spawns and talks to over its stdin and
+//! stdout, see [runner](crate::runner). Add a binary target to the
+//! module crate that contains the following, and install it with the
+//! `.module` extension (e.g. `sample_module.module`):
+//!
+//! ```rust,ignore
+//! utopia_module::declare_module_executable!(sample_module::SampleMod, sample_module::SampleMod::default);
+//! ```
+
+#![allow(clippy::tabs_in_doc_comments)]
+
+pub mod runner;
 
 use std::any::Any;
 
@@ -86,14 +103,102 @@ pub use redis;
 pub use futures;
 pub use utopia_common::module;
 
+/// version of the interface between µCore and its modules
+///
+/// every change of the interface bumps the version: the major version
+/// for changes that break existing modules, the minor version for
+/// additions older modules don't have to know about.
+///
+/// Modules loaded as shared library share µCore's types, whose layout
+/// changes with any addition, so they have to be built against the
+/// exact same version. Only [out-of-process](crate::runner) modules
+/// talk to µCore through a serialized protocol, which older minor
+/// versions still understand, see [accepts](InterfaceVersion::accepts).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterfaceVersion {
+	pub major: u32,
+	pub minor: u32
+}
+
+impl InterfaceVersion {
+	pub const fn new(major: u32, minor: u32) -> Self {
+		InterfaceVersion {
+			major,
+			minor
+		}
+	}
+
+	/// whether a core implementing this version can talk to a module
+	/// process built against module, which requires the same major and
+	/// an older or equal minor version
+	pub fn accepts(&self, module: InterfaceVersion) -> bool {
+		self.major == module.major && module.minor <= self.minor
+	}
+}
+
+impl std::fmt::Display for InterfaceVersion {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{}.{}", self.major, self.minor)
+	}
+}
+
+impl std::str::FromStr for InterfaceVersion {
+	type Err = String;
+
+	/// parses `major.minor`
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let invalid = || format!("invalid interface version {}, expected major.minor", s);
+		let (major, minor) = s.split_once('.').ok_or_else(invalid)?;
+		Ok(InterfaceVersion {
+			major: major.parse().map_err(|_| invalid())?,
+			minor: minor.parse().map_err(|_| invalid())?
+		})
+	}
+}
+
 /// ABI version
 ///
-/// string that is exported by a module and compared to the
-/// string provided by [utopia_module](crate) µCore was built
-/// against.
+/// version of the module interface provided by
+/// [utopia_module](crate). Modules report the version they were built
+/// against in their [ModuleDescriptor], µCore compares it to the one
+/// it was built against.
+///
+/// - 1.1: RemoveLibraryItem, UpdateLibraryItem and ReplaceAllItems
+/// - 2.0: tags in LibraryItemDetails
+/// - 2.1: UpdateLibraryItem and UninstallLibraryItem with their status
+///   signals
+/// - 2.2: InstallLibraryItem with its status signals, NotInstalled and
+///   Installing
+/// - 3.0: Progress and CancelOperation replace UpdateProgress and
+///   InstallProgress
+/// - 4.0: CrashInfo in Crashed
+/// - 5.0: µCore closes items with SIGTERM, CloseLibraryItem is only
+///   sent if configured
+/// - 6.0: LaunchOptions in LaunchLibraryItem
+/// - 6.1: closes_items in ModuleInfo, CloseLibraryItem is sent to the
+///   modules setting it instead of configured ones
+pub const MODULE_INTERFACE_VERSION: InterfaceVersion = InterfaceVersion::new(6, 1);
+
+/// name of the symbol a module library exports its [ModuleDescriptor]
+/// as
+pub const MODULE_DESCRIPTOR_SYMBOL: &[u8] = b"_utopia_module_descriptor";
+
+/// describes a module library to µCore, exported by
+/// [declare_module!](crate::declare_module)
 ///
-/// don't do anything with it
-pub const MODULE_INTERFACE_VERSION: &'static str = "0.0.0";
+/// the layout of the descriptor is stable, so µCore can check the
+/// version before calling into the module. New fields may only be
+/// added to the end, with a version bump.
+#[repr(C)]
+pub struct ModuleDescriptor {
+	/// version of the interface the module was built against. Always
+	/// the first field.
+	pub version: InterfaceVersion,
+	/// creates the module, returning a `Box<Box<dyn Module>>` as raw
+	/// pointer. Must only be called if the version is accepted.
+	pub create: unsafe extern "C" fn() -> *mut std::ffi::c_void
+}
 
 /// the sender for the channel that facilitates messages from the
 /// module to µCore
@@ -103,7 +208,7 @@ pub const MODULE_INTERFACE_VERSION: &'static str = "0.0.0";
 /// [ModuleCommands](utopia_common::module::ModuleCommands).
 ///
 /// ## usage in module runtime
-/// ```rust
+/// ```rust,ignore
 /// let mod_send: USend = mod_send;
 /// let r = mod_send.unbounded_send((self.id(), module::ModuleCommands::Refresh));
 /// if let Err(e) = r {
@@ -120,7 +225,7 @@ pub type USend = mpsc::UnboundedSender<(&'static str, module::ModuleCommands)>;
 /// a looped futures::select! is also possible.
 ///
 /// ## Example
-/// ```rust
+/// ```rust,ignore
 /// use anyhow::bail;
 /// use utopia_module::URecv;
 ///
@@ -163,7 +268,7 @@ pub type URes = (
 /// droped as soon as possible.
 ///
 /// ## Example
-/// ```rust
+/// ```rust,ignore
 /// use std::ops::DerefMut;
 /// use utopia_module::{UDb, redis::{Commands, Connection}};
 ///
@@ -221,12 +326,6 @@ pub trait Module: Any + Send + Sync {
 	/// [ThreadDeathExcuse](utopia_common::module::ThreadDeathExcuse)
 	/// for exceptions to let the thread function die.
 	fn thread(&self, mod_send: USend, core_recv: URecv) -> URes;
-
-	#[doc(hidden)]
-	#[inline(always)]
-	fn __abi_version(&self) -> &'static str {
-		MODULE_INTERFACE_VERSION
-	} // Prevent loading modules with newer/older ABI, to prevent segfaults.
 }
 
 /// spawns a synchronous runtime
@@ -255,7 +354,7 @@ macro_rules! spawn_runtime {
 /// macro wrapper.
 ///
 /// ## Example
-/// ```rust
+/// ```rust,ignore
 /// use futures::future;
 /// use utopia_module::{USend, URecv, URes, spawn_async_runtime};
 /// use utopia_common::module::ThreadDeathExcuse;
@@ -278,15 +377,16 @@ macro_rules! spawn_async_runtime {
 
 /// Declare a module and its constructor
 ///
-/// this macro creates a new c function called _module_create()
-/// which returns a raw pointer to this Module invoking this macro
-/// as created by the constructor. Consider implementing
+/// this macro exports a [ModuleDescriptor](crate::ModuleDescriptor)
+/// containing the interface version the module was built against and
+/// a function that creates this Module invoking this macro with the
+/// constructor. Consider implementing
 /// [Default](std::default::Default) and using it's
 /// [default() function](std::default::Default::default) like shown in
 /// the example example.
 ///
 /// ## Example
-/// ```rust
+/// ```rust,ignore
 /// use utopia_module::{Module, declare_module};
 ///
 /// #[derive(Debug, Default)]
@@ -301,14 +401,49 @@ macro_rules! spawn_async_runtime {
 #[macro_export]
 macro_rules! declare_module {
 	($module_type:ty, $constructor:path) => {
-		#[no_mangle]
-		pub extern fn _module_create() -> *mut $crate::Module {
+		unsafe extern "C" fn _utopia_module_create() -> *mut ::std::ffi::c_void {
 			// make sure the constructor is the correct type.
 			let constructor: fn() -> $module_type = $constructor;
 
 			let object = constructor();
-			let boxed: Box<$crate::Module> = Box::new(object);
-			Box::into_raw(boxed)
+			let boxed: Box<dyn $crate::Module> = Box::new(object);
+			// the fat pointer is boxed once more, so a thin pointer can be
+			// handed out
+			Box::into_raw(Box::new(boxed)) as *mut ::std::ffi::c_void
+		}
+
+		#[no_mangle]
+		#[allow(non_upper_case_globals)]
+		pub static _utopia_module_descriptor: $crate::ModuleDescriptor = $crate::ModuleDescriptor {
+			version: $crate::MODULE_INTERFACE_VERSION,
+			create: _utopia_module_create
+		};
+	};
+}
+
+/// Declare the main function of an out-of-process module
+///
+/// this macro creates a main function that runs the module created
+/// by the constructor with the [runner](crate::runner), so that µCore
+/// can spawn it as separate process. The same module may
+/// additionally be declared with [declare_module!](crate::declare_module)
+/// to build it as shared library as well.
+///
+/// ## Example
+/// ```rust,ignore
+/// // src/main.rs of the module crate
+/// use sample_module::SampleMod;
+///
+/// utopia_module::declare_module_executable!(SampleMod, SampleMod::default);
+/// ```
+#[macro_export]
+macro_rules! declare_module_executable {
+	($module_type:ty, $constructor:path) => {
+		fn main() {
+			// make sure the constructor is the correct type.
+			let constructor: fn() -> $module_type = $constructor;
+
+			std::process::exit($crate::runner::run(Box::new(constructor())));
 		}
 	};
 }
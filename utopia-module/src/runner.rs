@@ -0,0 +1,249 @@
+//! Runner for out-of-process modules
+//!
+//! Instead of being loaded into µCore as a shared library, a
+//! [Module](crate::Module) can be built as an executable that µCore
+//! spawns. It then talks to the core using the
+//! [ipc](utopia_common::ipc) protocol, so a crashing module only takes
+//! down its own process. The same module can be built as both kinds,
+//! see [declare_module_executable!](crate::declare_module_executable).
+//!
+//! As stdout carries the protocol, anything the module prints ends up
+//! on stderr, and stdin is empty. Processes the module spawns don't
+//! inherit the protocol, so the core still notices the module exiting
+//! while they run.
+use std::{error::Error,
+          fs::File,
+          io::{self, BufRead, BufReader, Write},
+          os::unix::io::{AsRawFd, FromRawFd},
+          sync::{mpsc as std_mpsc, Arc, RwLock},
+          thread,
+          time::Duration};
+
+use futures::{channel::mpsc, executor::block_on_stream};
+use utopia_common::ipc::{CoreMessage, ModuleMessage};
+
+use crate::{Module, UDb, MODULE_INTERFACE_VERSION};
+
+/// how long messages the module sent before its thread returned are
+/// waited for, in case it kept a sender around
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// duplicates fd for the protocol, so processes the module spawns
+/// don't inherit it and keep the connection to the core open
+fn dup_cloexec(fd: libc::c_int) -> io::Result<File> {
+	unsafe {
+		let dup = libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0);
+		if dup < 0 {
+			return Err(io::Error::last_os_error());
+		}
+		Ok(File::from_raw_fd(dup))
+	}
+}
+
+/// takes over stdout for the protocol and points the stdout of the
+/// process to stderr
+fn take_stdout() -> io::Result<File> {
+	io::stdout().flush()?;
+	let out = dup_cloexec(libc::STDOUT_FILENO)?;
+	if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(out)
+}
+
+/// takes over stdin for the protocol and points the stdin of the
+/// process to /dev/null
+fn take_stdin() -> io::Result<File> {
+	let input = dup_cloexec(libc::STDIN_FILENO)?;
+	let null = File::open("/dev/null")?;
+	if unsafe { libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO) } < 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(input)
+}
+
+fn write_message(out: &mut impl Write, msg: &ModuleMessage) -> io::Result<()> {
+	let mut line = serde_json::to_vec(msg)?;
+	line.push(b'\n');
+	out.write_all(&line)?;
+	out.flush()
+}
+
+/// reads the next message of the core, None if it closed the
+/// connection
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<CoreMessage>> {
+	let mut line = String::new();
+	if input.read_line(&mut line)? == 0 {
+		return Ok(None);
+	}
+	Ok(Some(serde_json::from_str(&line)?))
+}
+
+/// runs module as an out-of-process module, speaking the
+/// [ipc](utopia_common::ipc) protocol on stdin and stdout
+///
+/// returns once the module thread returned, with the exit code for
+/// the process.
+pub fn run(module: Box<dyn Module>) -> i32 {
+	let id = module.id();
+	match serve(module) {
+		Ok(code) => code,
+		Err(e) => {
+			eprintln!("Module {} failed: {}", id, e);
+			1
+		}
+	}
+}
+
+fn serve(mut module: Box<dyn Module>) -> Result<i32, Box<dyn Error>> {
+	let mut out = take_stdout()?;
+	let mut input = BufReader::new(take_stdin()?);
+	write_message(&mut out, &ModuleMessage::Hello {
+		id: module.id().to_owned(),
+		abi_version: MODULE_INTERFACE_VERSION.to_string(),
+		info: module.get_module_info()
+	})?;
+	let database = match read_message(&mut input)? {
+		Some(CoreMessage::Init {
+			database
+		}) => database,
+		// the core decided not to load the module
+		None => return Ok(0),
+		Some(msg) => return Err(format!("expected Init, got {:?}", msg).into())
+	};
+	let db: UDb = Arc::new(RwLock::new(redis::Client::open(database.as_str())?.get_connection()?));
+	module.init(db);
+	let module = Arc::new(module);
+
+	let (out_send, out_recv) = std_mpsc::channel::<ModuleMessage>();
+	let writer = thread::spawn(move || -> io::Result<()> {
+		for msg in out_recv {
+			let last = matches!(msg, ModuleMessage::Died(_) | ModuleMessage::Error(_));
+			write_message(&mut out, &msg)?;
+			if last {
+				break;
+			}
+		}
+		Ok(())
+	});
+
+	let (mod_send, mod_recv) = mpsc::unbounded();
+	let (drained_send, drained) = std_mpsc::channel::<()>();
+	let forward = out_send.clone();
+	thread::spawn(move || {
+		for (_, cmd) in block_on_stream(mod_recv) {
+			if forward.send(ModuleMessage::Command(cmd)).is_err() {
+				break;
+			}
+		}
+		let _ = drained_send.send(());
+	});
+
+	let (core_send, core_recv) = mpsc::unbounded();
+	let reader = module.clone();
+	thread::spawn(move || {
+		loop {
+			match read_message(&mut input) {
+				Ok(Some(CoreMessage::Command(cmd))) => {
+					if core_send.unbounded_send(cmd).is_err() {
+						break;
+					}
+				},
+				Ok(Some(msg)) => eprintln!("Ignoring unexpected message from core: {:?}", msg),
+				Ok(None) => break,
+				Err(e) => {
+					eprintln!("Lost connection to core: {}", e);
+					break;
+				}
+			}
+		}
+		// the core closing the connection is the request to stop, just
+		// like unloading an in-process module
+		reader.deinit();
+		drop(core_send);
+	});
+
+	let (_, res) = module.thread(mod_send, core_recv);
+	let _ = drained.recv_timeout(DRAIN_TIMEOUT);
+	let (msg, code) = match res {
+		Ok(excuse) => (ModuleMessage::Died(excuse), 0),
+		Err(e) => (ModuleMessage::Error(e.to_string()), 1)
+	};
+	// the writer only fails if the core is gone already
+	let _ = out_send.send(msg);
+	writer.join().map_err(|_| "the writer thread panicked")??;
+	Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{process::{Command, Stdio},
+	          sync::mpsc as std_mpsc};
+
+	use utopia_common::module;
+
+	use super::*;
+
+	/// set in the module process the test spawns
+	const MODULE_ENV: &str = "UTOPIA_TEST_MODULE";
+
+	struct SpawningModule;
+	impl Module for SpawningModule {
+		fn id(&self) -> &'static str {
+			"org.utopia.test.spawning"
+		}
+
+		/// the protocol was taken over by now, so this is just like
+		/// spawning a game. It outlives the module, which doesn't wait for
+		/// it.
+		#[allow(clippy::zombie_processes)]
+		fn get_module_info(&self) -> module::ModuleInfo {
+			Command::new("sleep").arg("10").spawn().expect("Failed spawning sleep");
+			module::ModuleInfo {
+				name: "Spawning".into(),
+				url: None,
+				developer: "µCore".into(),
+				developer_url: None,
+				description: None,
+				icon: None,
+				closes_items: false
+			}
+		}
+
+		fn thread(&self, _mod_send: crate::USend, _core_recv: crate::URecv) -> crate::URes {
+			(self.id(), Ok(module::ThreadDeathExcuse::Other("test".into())))
+		}
+	}
+
+	#[test]
+	fn spawned_processes_dont_keep_the_protocol_open() {
+		if std::env::var_os(MODULE_ENV).is_some() {
+			std::process::exit(run(Box::new(SpawningModule)));
+		}
+		let mut module = Command::new(std::env::current_exe().unwrap())
+			.args(["runner::tests::spawned_processes_dont_keep_the_protocol_open", "--exact", "--nocapture", "-q"])
+			.env(MODULE_ENV, "1")
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.spawn()
+			.unwrap();
+		let stdout = BufReader::new(module.stdout.take().unwrap());
+		let (line_send, lines) = std_mpsc::channel();
+		thread::spawn(move || {
+			for line in stdout.lines() {
+				let _ = line_send.send(line.unwrap());
+			}
+		});
+		// the test harness of the module process prints to stdout too
+		loop {
+			let line = lines.recv_timeout(Duration::from_secs(5)).expect("No hello received");
+			if let Ok(ModuleMessage::Hello { .. }) = serde_json::from_str(&line) {
+				break;
+			}
+		}
+		// not sending Init makes the module exit
+		drop(module.stdin.take());
+		assert!(module.wait().unwrap().success());
+		assert_eq!(lines.recv_timeout(Duration::from_secs(5)), Err(std_mpsc::RecvTimeoutError::Disconnected));
+	}
+}